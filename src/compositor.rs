@@ -0,0 +1,72 @@
+//! Notices compositing managers starting and stopping, through the owner of the
+//! `_NET_WM_CM_S<screen>` selection (EWMH).
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::Event;
+use x11rb::protocol::xfixes::{ConnectionExt as XfixesConnectionExt, SelectionEvent, SelectionEventMask};
+use x11rb::protocol::xproto::{Atom, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+
+use crate::error::Result;
+
+pub struct CompositorWatch {
+    selection: Atom,
+    owner: Window,
+    /// owner changes are announced by XFixes; otherwise `poll` asks for them
+    xfixes: bool,
+}
+
+impl CompositorWatch {
+    /// Starts watching the compositor selection of `screen`.
+    pub fn new(conn: &RustConnection, screen: usize) -> Result<Self> {
+        let root = conn.setup().roots[screen].root;
+        let name = format!("_NET_WM_CM_S{}", screen);
+        let selection = conn.intern_atom(false, name.as_bytes())?.reply()?.atom;
+
+        let xfixes = conn.extension_information(x11rb::protocol::xfixes::X11_EXTENSION_NAME)?.is_some();
+        if xfixes {
+            // the version has to be negotiated before any other request
+            conn.xfixes_query_version(1, 0)?.reply()?;
+            conn.xfixes_select_selection_input(
+                root,
+                selection,
+                SelectionEventMask::SET_SELECTION_OWNER
+                    | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                    | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+            )?;
+        }
+        let owner = conn.get_selection_owner(selection)?.reply()?.owner;
+        Ok(Self { selection, owner, xfixes })
+    }
+
+    /// True while a compositing manager runs.
+    pub fn is_active(&self) -> bool {
+        self.owner != x11rb::NONE
+    }
+
+    /// Returns true if the compositor started or stopped.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let Event::XfixesSelectionNotify(e) = event else { return false };
+        if e.selection != self.selection {
+            return false;
+        }
+        let owner = if e.subtype == SelectionEvent::SET_SELECTION_OWNER { e.owner } else { x11rb::NONE };
+        self.set_owner(owner)
+    }
+
+    /// Asks for the owner again on servers without XFixes, and does nothing otherwise.
+    /// Returns true if the compositor started or stopped.
+    pub fn poll(&mut self, conn: &RustConnection) -> Result<bool> {
+        if self.xfixes {
+            return Ok(false);
+        }
+        let owner = conn.get_selection_owner(self.selection)?.reply()?.owner;
+        Ok(self.set_owner(owner))
+    }
+
+    fn set_owner(&mut self, owner: Window) -> bool {
+        let was_active = self.is_active();
+        self.owner = owner;
+        self.is_active() != was_active
+    }
+}
@@ -0,0 +1,193 @@
+//! Tracks which monitors are hidden behind fullscreen or maximized windows (EWMH).
+
+use x11rb::errors::ReplyError;
+use x11rb::protocol::Event;
+use x11rb::protocol::xproto::{
+    Atom,
+    AtomEnum,
+    ChangeWindowAttributesAux,
+    ConnectionExt,
+    EventMask,
+    MapState,
+    Window,
+};
+use x11rb::rust_connection::RustConnection;
+
+struct Atoms {
+    client_list_stacking: Atom,
+    wm_state: Atom,
+    fullscreen: Atom,
+    maximized_vert: Atom,
+    maximized_horz: Atom,
+    hidden: Atom,
+}
+
+impl Atoms {
+    fn new(conn: &RustConnection) -> Result<Self, ReplyError> {
+        let intern = |name: &[u8]| -> Result<Atom, ReplyError> {
+            Ok(conn.intern_atom(false, name)?.reply()?.atom)
+        };
+        Ok(Self {
+            client_list_stacking: intern(b"_NET_CLIENT_LIST_STACKING")?,
+            wm_state: intern(b"_NET_WM_STATE")?,
+            fullscreen: intern(b"_NET_WM_STATE_FULLSCREEN")?,
+            maximized_vert: intern(b"_NET_WM_STATE_MAXIMIZED_VERT")?,
+            maximized_horz: intern(b"_NET_WM_STATE_MAXIMIZED_HORZ")?,
+            hidden: intern(b"_NET_WM_STATE_HIDDEN")?,
+        })
+    }
+}
+
+pub struct CoverTracker {
+    atoms: Atoms,
+    root: Window,
+    monitors: Vec<[u16; 4]>,
+
+    clients: Vec<Window>,
+    covered: Vec<bool>,
+    dirty: bool,
+}
+
+impl CoverTracker {
+    /// Starts watching the client list of `root`.
+    /// `monitors` are `[x, y, width, height]` in root coordinates.
+    pub fn new(conn: &RustConnection, root: Window, monitors: &[[u16; 4]]) -> crate::Result<Self> {
+        let atoms = Atoms::new(conn)?;
+
+        conn.change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?;
+
+        let mut tracker = Self {
+            atoms,
+            root,
+            monitors: monitors.to_vec(),
+            clients: Vec::new(),
+            covered: vec![false; monitors.len()],
+            dirty: true,
+        };
+        tracker.update_clients(conn)?;
+        Ok(tracker)
+    }
+
+    /// Follows a change of the monitor layout.
+    pub fn set_monitors(&mut self, monitors: &[[u16; 4]]) {
+        self.monitors = monitors.to_vec();
+        self.covered = vec![false; monitors.len()];
+        self.dirty = true;
+    }
+
+    /// Which monitors are completely hidden, in the order given to `new`.
+    pub fn covered(&mut self, conn: &RustConnection) -> Result<&[bool], ReplyError> {
+        if self.dirty {
+            self.recompute(conn)?;
+            self.dirty = false;
+        }
+        Ok(&self.covered)
+    }
+
+    /// Feeds an X event; anything that may change the stacking or window states marks the
+    /// state as stale.
+    pub fn handle_event(&mut self, conn: &RustConnection, event: &Event) -> Result<(), ReplyError> {
+        match event {
+            Event::PropertyNotify(e) if e.window == self.root && e.atom == self.atoms.client_list_stacking => {
+                self.update_clients(conn)?;
+            }
+            Event::PropertyNotify(e) if e.atom == self.atoms.wm_state => {
+                self.dirty = true;
+            }
+            Event::ConfigureNotify(e) if self.clients.contains(&e.window) => {
+                self.dirty = true;
+            }
+            Event::MapNotify(e) if self.clients.contains(&e.window) => {
+                self.dirty = true;
+            }
+            Event::UnmapNotify(e) if self.clients.contains(&e.window) => {
+                self.dirty = true;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn update_clients(&mut self, conn: &RustConnection) -> Result<(), ReplyError> {
+        let clients = conn
+            .get_property(false, self.root, self.atoms.client_list_stacking, AtomEnum::WINDOW, 0, u32::MAX)?
+            .reply()?
+            .value32()
+            .map(|v| v.collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        for &w in clients.iter().filter(|w| !self.clients.contains(w)) {
+            // the window may already be gone; ignore the error
+            conn.change_window_attributes(
+                w,
+                &ChangeWindowAttributesAux::new()
+                    .event_mask(EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY),
+            )?.ignore_error();
+        }
+
+        self.clients = clients;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn recompute(&mut self, conn: &RustConnection) -> Result<(), ReplyError> {
+        self.covered.iter_mut().for_each(|c| *c = false);
+
+        for &w in &self.clients {
+            // windows can disappear at any time; treat failures as "not covering"
+            let Some(rect) = self.cover_rect(conn, w)? else { continue };
+
+            for (m, covered) in self.monitors.iter().zip(self.covered.iter_mut()) {
+                let (mx, my) = (m[0] as i32, m[1] as i32);
+                let (mw, mh) = (m[2] as i32, m[3] as i32);
+                if rect[0] <= mx && rect[1] <= my
+                    && rect[0] + rect[2] >= mx + mw
+                    && rect[1] + rect[3] >= my + mh {
+                    *covered = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The area hidden by `w` in root coordinates, if it hides anything at all.
+    fn cover_rect(&self, conn: &RustConnection, w: Window) -> Result<Option<[i32; 4]>, ReplyError> {
+        let Ok(attrs) = conn.get_window_attributes(w)?.reply() else { return Ok(None) };
+        if attrs.map_state != MapState::VIEWABLE {
+            return Ok(None);
+        }
+
+        let Ok(state) = conn.get_property(false, w, self.atoms.wm_state, AtomEnum::ATOM, 0, 64)?.reply() else {
+            return Ok(None);
+        };
+        let state = state.value32().map(|v| v.collect::<Vec<_>>()).unwrap_or_default();
+        if state.contains(&self.atoms.hidden) {
+            return Ok(None);
+        }
+        let fullscreen = state.contains(&self.atoms.fullscreen);
+        let maximized = state.contains(&self.atoms.maximized_vert) && state.contains(&self.atoms.maximized_horz);
+        if !fullscreen && !maximized {
+            return Ok(None);
+        }
+
+        let Ok(geom) = conn.get_geometry(w)?.reply() else { return Ok(None) };
+        let Ok(pos) = conn.translate_coordinates(w, self.root, 0, 0)?.reply() else { return Ok(None) };
+        let (x, y) = (pos.dst_x as i32, pos.dst_y as i32);
+        let (w, h) = (geom.width as i32, geom.height as i32);
+
+        if fullscreen {
+            return Ok(Some([x, y, w, h]));
+        }
+
+        // a maximized client leaves room for its decorations and panels, so it never spans the
+        // whole monitor; count it as covering the monitor its center is on.
+        let (cx, cy) = (x + w / 2, y + h / 2);
+        Ok(self.monitors.iter().find(|m| {
+            let (mx, my) = (m[0] as i32, m[1] as i32);
+            cx >= mx && cy >= my && cx < mx + m[2] as i32 && cy < my + m[3] as i32
+        }).map(|m| [m[0] as i32, m[1] as i32, m[2] as i32, m[3] as i32]))
+    }
+}
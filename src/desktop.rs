@@ -0,0 +1,318 @@
+//! Desktop windows below all others, one per monitor, that frames are presented to straight
+//! from the gpu. An alternative to the root window for desktops that paint over it, and one
+//! that skips reading frames back.
+
+use std::ffi::{c_void, CString};
+use std::sync::Arc;
+use std::time::Duration;
+
+use raw_window_handle::{
+    HasRawDisplayHandle,
+    HasRawWindowHandle,
+    RawDisplayHandle,
+    RawWindowHandle,
+    XcbDisplayHandle,
+    XcbWindowHandle,
+};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    Atom,
+    AtomEnum,
+    ConfigureWindowAux,
+    ConnectionExt,
+    CreateWindowAux,
+    EventMask,
+    PropMode,
+    StackMode,
+    Window,
+    WindowClass,
+};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::xcb_ffi::XCBConnection;
+
+use crate::error::{Error, Result};
+use crate::monitor::Monitor;
+use crate::render::{OutputFormat, Renderer, RendererBuilder};
+use crate::scene::Scene;
+use crate::texture::TextureCache;
+
+/// frames go to the windows in this format
+const FORMAT: OutputFormat = OutputFormat::Bgra8;
+
+struct Atoms {
+    wm_window_type: Atom,
+    wm_window_type_desktop: Atom,
+    wm_state: Atom,
+    /// states for `wm_state`: below, sticky, skip taskbar, skip pager
+    states: [Atom; 4],
+}
+
+impl Atoms {
+    fn new(conn: &RustConnection) -> Result<Self> {
+        let intern = |name: &[u8]| -> Result<Atom> {
+            Ok(conn.intern_atom(false, name)?.reply()?.atom)
+        };
+        Ok(Self {
+            wm_window_type: intern(b"_NET_WM_WINDOW_TYPE")?,
+            wm_window_type_desktop: intern(b"_NET_WM_WINDOW_TYPE_DESKTOP")?,
+            wm_state: intern(b"_NET_WM_STATE")?,
+            states: [
+                intern(b"_NET_WM_STATE_BELOW")?,
+                intern(b"_NET_WM_STATE_STICKY")?,
+                intern(b"_NET_WM_STATE_SKIP_TASKBAR")?,
+                intern(b"_NET_WM_STATE_SKIP_PAGER")?,
+            ],
+        })
+    }
+}
+
+/// What wgpu needs to find a window: a libxcb connection and the window id.
+struct XcbWindow {
+    connection: *mut c_void,
+    screen: i32,
+    window: Window,
+}
+
+unsafe impl HasRawWindowHandle for XcbWindow {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        let mut handle = XcbWindowHandle::empty();
+        handle.window = self.window;
+        RawWindowHandle::Xcb(handle)
+    }
+}
+
+unsafe impl HasRawDisplayHandle for XcbWindow {
+    fn raw_display_handle(&self) -> RawDisplayHandle {
+        let mut handle = XcbDisplayHandle::empty();
+        handle.connection = self.connection;
+        handle.screen = self.screen;
+        RawDisplayHandle::Xcb(handle)
+    }
+}
+
+struct DesktopWindow {
+    window: Window,
+    surface: wgpu::Surface,
+    config: wgpu::SurfaceConfiguration,
+}
+
+/// A gpu device that can draw to windows, shared by the windows of several screens.
+#[derive(Clone)]
+pub struct WindowGpu {
+    instance: Arc<wgpu::Instance>,
+    adapter: Arc<wgpu::Adapter>,
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+}
+
+impl WindowGpu {
+    pub fn device(&self) -> (&Arc<wgpu::Device>, &Arc<wgpu::Queue>) {
+        (&self.device, &self.queue)
+    }
+}
+
+pub struct DesktopWindows<'c> {
+    // dropped in order: surfaces before the connection they were created on
+    windows: Vec<DesktopWindow>,
+    gpu: WindowGpu,
+    /// wgpu needs a libxcb connection; windows are created and managed on `conn`
+    xcb: XCBConnection,
+
+    conn: &'c RustConnection,
+    screen: usize,
+    root: Window,
+    atoms: Atoms,
+}
+
+impl<'c> DesktopWindows<'c> {
+    /// Creates a window for each of `monitors` on `screen` of `conn`, which is connected to
+    /// `display` (`$DISPLAY` if `None`). The windows are drawn to with `gpu`, or with a new
+    /// device that can draw to them; see `gpu` for sharing it with other screens.
+    pub async fn new(
+        conn: &'c RustConnection,
+        display: Option<&str>,
+        screen: usize,
+        monitors: &[Monitor],
+        gpu: Option<WindowGpu>,
+    ) -> Result<DesktopWindows<'c>> {
+        let display = display.map(|d| CString::new(d).map_err(|e| Error::Surface(e.to_string()))).transpose()?;
+        let (xcb, _) = XCBConnection::connect(display.as_deref())?;
+        let instance = match &gpu {
+            Some(gpu) => gpu.instance.clone(),
+            None => Arc::new(wgpu::Instance::new(wgpu::InstanceDescriptor::default())),
+        };
+        let root = conn.setup().roots[screen].root;
+        let atoms = Atoms::new(conn)?;
+
+        let mut windows = Vec::new();
+        for &m in monitors {
+            windows.push(create_window(conn, &xcb, &instance, screen, root, &atoms, m)?);
+        }
+
+        let gpu = match gpu {
+            Some(gpu) => gpu,
+            None => {
+                let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: windows.first().map(|w| &w.surface),
+                    force_fallback_adapter: false,
+                }).await.ok_or(Error::NoAdapter)?;
+                let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor::default(), None).await?;
+                WindowGpu { instance, adapter: Arc::new(adapter), device: Arc::new(device), queue: Arc::new(queue) }
+            }
+        };
+
+        let desktop = Self {
+            windows,
+            gpu,
+            xcb,
+            conn,
+            screen,
+            root,
+            atoms,
+        };
+        for w in &desktop.windows {
+            desktop.configure_surface(w)?;
+        }
+        Ok(desktop)
+    }
+
+    /// The device the windows are drawn with.
+    pub fn gpu(&self) -> &WindowGpu {
+        &self.gpu
+    }
+
+    /// A renderer for drawing `scene` into the windows. `cache` must be on our device.
+    pub async fn renderer(
+        &self,
+        size: [u16; 2],
+        monitors: &[Monitor],
+        scene: &Scene,
+        cache: &mut TextureCache,
+    ) -> Result<Renderer<'static>> {
+        RendererBuilder::new(size)
+            .monitors(monitors)
+            .format(FORMAT)
+            .readback(false)
+            .cached_scene(scene, cache)?
+            .build()
+            .await
+    }
+
+    /// Replaces the windows with ones for `monitors`.
+    pub fn configure(&mut self, monitors: &[Monitor]) -> Result<()> {
+        self.destroy_windows()?;
+        for &m in monitors {
+            let w = create_window(self.conn, &self.xcb, &self.gpu.instance, self.screen, self.root, &self.atoms, m)?;
+            self.configure_surface(&w)?;
+            self.windows.push(w);
+        }
+        Ok(())
+    }
+
+    /// Draws the frame at time `t` into the windows of the monitors marked in `active`.
+    pub fn draw(&mut self, rnd: &mut Renderer, t: Duration, active: &[bool]) -> Result<()> {
+        for (i, (w, &active)) in self.windows.iter().zip(active).enumerate() {
+            if !active {
+                continue;
+            }
+            let frame = match w.surface.get_current_texture() {
+                Ok(frame) => frame,
+                // resized or lost behind our back; try again next frame
+                Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+                    w.surface.configure(&self.gpu.device, &w.config);
+                    continue;
+                }
+                Err(wgpu::SurfaceError::Timeout) => continue,
+                Err(e) => return Err(Error::Surface(e.to_string())),
+            };
+            let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
+            rnd.render_to(t, i, &view);
+            frame.present();
+        }
+        Ok(())
+    }
+
+    /// True if `window` is one of ours, e.g. to redraw on its expose events.
+    pub fn contains(&self, window: Window) -> bool {
+        self.windows.iter().any(|w| w.window == window)
+    }
+
+    /// Destroys the windows.
+    pub fn close(mut self) -> Result<()> {
+        self.destroy_windows()?;
+        self.conn.sync()?;
+        Ok(())
+    }
+
+    fn destroy_windows(&mut self) -> Result<()> {
+        for w in self.windows.drain(..) {
+            // the surface must go before its window
+            drop(w.surface);
+            self.conn.destroy_window(w.window)?;
+        }
+        Ok(())
+    }
+
+    fn configure_surface(&self, w: &DesktopWindow) -> Result<()> {
+        let caps = w.surface.get_capabilities(&self.gpu.adapter);
+        if !caps.formats.contains(&w.config.format) {
+            return Err(Error::Surface(format!("{:?} not supported, only {:?}", w.config.format, caps.formats)));
+        }
+        w.surface.configure(&self.gpu.device, &w.config);
+        Ok(())
+    }
+}
+
+/// Creates a desktop window covering `monitor` and a surface for it.
+fn create_window(
+    conn: &RustConnection,
+    xcb: &XCBConnection,
+    instance: &wgpu::Instance,
+    screen: usize,
+    root: Window,
+    atoms: &Atoms,
+    monitor: Monitor,
+) -> Result<DesktopWindow> {
+    let Monitor { x, y, width, height, .. } = monitor;
+    let window = conn.generate_id()?;
+    conn.create_window(
+        x11rb::COPY_DEPTH_FROM_PARENT,
+        window,
+        root,
+        x,
+        y,
+        width,
+        height,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        x11rb::COPY_FROM_PARENT,
+        // no background, so that the server doesn't clear what we present
+        &CreateWindowAux::new().background_pixmap(x11rb::NONE).event_mask(EventMask::EXPOSURE),
+    )?;
+    conn.change_property32(PropMode::REPLACE, window, atoms.wm_window_type, AtomEnum::ATOM, &[atoms.wm_window_type_desktop])?;
+    conn.change_property32(PropMode::REPLACE, window, atoms.wm_state, AtomEnum::ATOM, &atoms.states)?;
+    conn.change_property8(PropMode::REPLACE, window, AtomEnum::WM_NAME, AtomEnum::STRING, b"xbg")?;
+    conn.change_property8(PropMode::REPLACE, window, AtomEnum::WM_CLASS, AtomEnum::STRING, b"xbg\0xbg\0")?;
+    conn.map_window(window)?;
+    // window managers may place it elsewhere; without one, this puts it below everything
+    conn.configure_window(window, &ConfigureWindowAux::new().x(i32::from(x)).y(i32::from(y)).stack_mode(StackMode::BELOW))?;
+    // the window has to exist before wgpu looks it up through the other connection
+    conn.sync()?;
+
+    let handle = XcbWindow { connection: xcb.get_raw_xcb_connection(), screen: screen as i32, window };
+    let surface = unsafe { instance.create_surface(&handle) }
+        .map_err(|e| Error::Surface(e.to_string()))?;
+
+    let config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: FORMAT.texture_format(),
+        width: width.into(),
+        height: height.into(),
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+    };
+    Ok(DesktopWindow { window, surface, config })
+}
@@ -0,0 +1,115 @@
+//! Errors returned by the library.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use x11rb::errors::{ConnectError, ConnectionError, ReplyError, ReplyOrIdError};
+
+#[derive(Debug)]
+pub enum Error {
+    /// the X server could not be reached
+    Connect(ConnectError),
+    /// the connection to the X server broke
+    Connection(ConnectionError),
+    /// the X server answered a request with an error
+    Request(x11rb::x11_utils::X11Error),
+    /// the connection ran out of resource ids
+    IdsExhausted,
+    /// a required X extension is not available, by name
+    MissingExtension(&'static str),
+    /// a shared memory segment could not be created or mapped
+    Shm(std::io::Error),
+    /// the root visual is something we can't render into
+    UnsupportedFormat(String),
+    /// no gpu adapter, not even a software one
+    NoAdapter,
+    Device(wgpu::RequestDeviceError),
+    /// a window surface could not be created or drawn to
+    Surface(String),
+    /// shader or pipeline validation failed
+    Shader(String),
+    /// a rendered frame could not be read back, e.g. because the device was lost
+    Readback(String),
+    /// an image could not be read or decoded
+    Image { label: String, source: image::ImageError },
+    /// a file could not be read or written
+    Io { path: PathBuf, source: std::io::Error },
+    /// a scene file is not valid
+    Parse { path: PathBuf, source: toml::de::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(e) => write!(f, "failed to connect to the X server: {}", e),
+            Error::Connection(e) => write!(f, "X connection error: {}", e),
+            Error::Request(e) => write!(f, "X request failed: {:?}", e.error_kind),
+            Error::IdsExhausted => write!(f, "X resource ids exhausted"),
+            Error::MissingExtension(name) => write!(f, "X extension {} is not supported", name),
+            Error::Shm(e) => write!(f, "shared memory error: {}", e),
+            Error::UnsupportedFormat(what) => write!(f, "unsupported pixel format: {}", what),
+            Error::NoAdapter => write!(f, "no suitable gpu adapter found"),
+            Error::Device(e) => write!(f, "failed to open gpu device: {}", e),
+            Error::Surface(e) => write!(f, "surface error: {}", e),
+            Error::Shader(e) => write!(f, "shader error: {}", e),
+            Error::Readback(e) => write!(f, "failed to read back frame: {}", e),
+            Error::Image { label, source } => write!(f, "failed to load {}: {}", label, source),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Parse { path, source } => write!(f, "failed to parse {}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connect(e) => Some(e),
+            Error::Connection(e) => Some(e),
+            Error::Shm(e) => Some(e),
+            Error::Device(e) => Some(e),
+            Error::Image { source, .. } => Some(source),
+            Error::Io { source, .. } => Some(source),
+            Error::Parse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConnectError> for Error {
+    fn from(e: ConnectError) -> Self {
+        Error::Connect(e)
+    }
+}
+
+impl From<ConnectionError> for Error {
+    fn from(e: ConnectionError) -> Self {
+        Error::Connection(e)
+    }
+}
+
+impl From<ReplyError> for Error {
+    fn from(e: ReplyError) -> Self {
+        match e {
+            ReplyError::ConnectionError(e) => Error::Connection(e),
+            ReplyError::X11Error(e) => Error::Request(e),
+        }
+    }
+}
+
+impl From<ReplyOrIdError> for Error {
+    fn from(e: ReplyOrIdError) -> Self {
+        match e {
+            ReplyOrIdError::IdsExhausted => Error::IdsExhausted,
+            ReplyOrIdError::ConnectionError(e) => Error::Connection(e),
+            ReplyOrIdError::X11Error(e) => Error::Request(e),
+        }
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for Error {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        Error::Device(e)
+    }
+}
@@ -0,0 +1,155 @@
+//! Pixel layout of the root window, and conversion of rendered frames into it.
+
+use x11rb::protocol::xproto::{ImageOrder, Screen, Setup, VisualClass};
+
+use crate::error::{Error, Result};
+use crate::render::{OutputFormat, RowLayout};
+
+/// How the X server expects pixels of the root visual to be laid out in an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub depth: u8,
+    pub bits_per_pixel: u8,
+    /// rows are padded to a multiple of this many bits
+    pub scanline_pad: u8,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    pub big_endian: bool,
+}
+
+impl PixelFormat {
+    /// 32 bit little endian pixels with blue in the lowest byte and the top byte unused.
+    pub const BGRX: PixelFormat = PixelFormat {
+        depth: 24,
+        bits_per_pixel: 32,
+        scanline_pad: 32,
+        red_mask: 0xff0000,
+        green_mask: 0xff00,
+        blue_mask: 0xff,
+        big_endian: false,
+    };
+
+    /// Reads the format of the root visual of `screen`.
+    /// Only TrueColor and DirectColor visuals are supported.
+    pub fn from_setup(setup: &Setup, screen: &Screen) -> Result<Self> {
+        let visual = screen.allowed_depths.iter()
+            .flat_map(|d| d.visuals.iter())
+            .find(|v| v.visual_id == screen.root_visual)
+            .ok_or_else(|| Error::UnsupportedFormat("root visual not found".into()))?;
+        if visual.class != VisualClass::TRUE_COLOR && visual.class != VisualClass::DIRECT_COLOR {
+            return Err(Error::UnsupportedFormat(format!("root visual class {:?}", visual.class)));
+        }
+
+        let format = setup.pixmap_formats.iter()
+            .find(|f| f.depth == screen.root_depth)
+            .ok_or_else(|| Error::UnsupportedFormat(format!("no pixmap format for depth {}", screen.root_depth)))?;
+        if !matches!(format.bits_per_pixel, 16 | 24 | 32) {
+            return Err(Error::UnsupportedFormat(format!("{} bits per pixel", format.bits_per_pixel)));
+        }
+
+        Ok(Self {
+            depth: screen.root_depth,
+            bits_per_pixel: format.bits_per_pixel,
+            scanline_pad: format.scanline_pad,
+            red_mask: visual.red_mask,
+            green_mask: visual.green_mask,
+            blue_mask: visual.blue_mask,
+            big_endian: setup.image_byte_order == ImageOrder::MSB_FIRST,
+        })
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel as usize / 8
+    }
+
+    /// Bytes per row of an image `width` pixels wide.
+    pub fn stride(&self, width: u16) -> usize {
+        let pad = self.scanline_pad.max(8) as usize;
+        (width as usize * self.bits_per_pixel as usize).div_ceil(pad) * pad / 8
+    }
+
+    /// The gpu output format closest to this one.
+    pub fn output_format(&self) -> OutputFormat {
+        if self.depth == 30 {
+            OutputFormat::Bgr10
+        } else {
+            OutputFormat::Bgra8
+        }
+    }
+
+    /// True if frames rendered in `output` can be copied as they are.
+    pub fn matches(&self, output: OutputFormat) -> bool {
+        let masks = match output {
+            OutputFormat::Bgra8 => (0xff0000, 0xff00, 0xff),
+            OutputFormat::Bgr10 => (0x3ff00000, 0xffc00, 0x3ff),
+        };
+        self.bits_per_pixel == 32 && !self.big_endian
+            && (self.red_mask, self.green_mask, self.blue_mask) == masks
+    }
+
+    /// Writes a frame rendered in `output` with layout `rows` into `dst`, an image in this
+    /// format with rows `self.stride(width)` bytes apart.
+    pub fn write_frame(&self, output: OutputFormat, rows: RowLayout, src: &[u8], dst: &mut [u8]) {
+        let stride = dst.len() / rows.rows.max(1);
+        if self.matches(output) && stride == rows.bytes_per_row {
+            rows.copy_to(src, dst);
+            return;
+        }
+
+        let (src_bits, src_shift) = match output {
+            OutputFormat::Bgra8 => (8, [16, 8, 0]),
+            OutputFormat::Bgr10 => (10, [20, 10, 0]),
+        };
+        let channels = [self.red_mask, self.green_mask, self.blue_mask].map(Channel::from_mask);
+        let bpp = self.bytes_per_pixel();
+        let width = rows.bytes_per_row / 4;
+
+        for (dst, src) in dst.chunks_exact_mut(stride).zip(src.chunks(rows.padded_bytes_per_row)) {
+            for (dst, src) in dst[..width * bpp].chunks_exact_mut(bpp).zip(src.chunks_exact(4)) {
+                let p = u32::from_le_bytes([src[0], src[1], src[2], src[3]]);
+                let mut v = 0;
+                for (c, shift) in channels.iter().zip(src_shift) {
+                    v |= c.encode((p >> shift) & ((1 << src_bits) - 1), src_bits);
+                }
+                let bytes = v.to_le_bytes();
+                if self.big_endian {
+                    for (d, b) in dst.iter_mut().zip(bytes[..bpp].iter().rev()) {
+                        *d = *b;
+                    }
+                } else {
+                    dst.copy_from_slice(&bytes[..bpp]);
+                }
+            }
+        }
+    }
+}
+
+/// Position and width of a color channel within a pixel value.
+struct Channel {
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    fn from_mask(mask: u32) -> Self {
+        Self { shift: mask.trailing_zeros() % 32, bits: mask.count_ones() }
+    }
+
+    /// Rescales `value`, `bits` wide, to this channel and moves it into place.
+    fn encode(&self, value: u32, bits: u32) -> u32 {
+        let value = if self.bits <= bits {
+            value >> (bits - self.bits)
+        } else {
+            // replicate the high bits so that full intensity stays full
+            let mut v = 0;
+            let mut filled = 0;
+            while filled < self.bits {
+                v = (v << bits) | value;
+                filled += bits;
+            }
+            v >> (filled - self.bits)
+        };
+        value << self.shift
+    }
+}
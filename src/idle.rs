@@ -0,0 +1,58 @@
+//! Display power and user idleness, via the DPMS and MIT-SCREEN-SAVER extensions.
+
+use std::time::Duration;
+
+use x11rb::connection::RequestConnection;
+use x11rb::errors::ReplyError;
+use x11rb::protocol::dpms::{ConnectionExt as DpmsConnectionExt, DPMSMode};
+use x11rb::protocol::screensaver::{ConnectionExt as ScreensaverConnectionExt, State};
+use x11rb::protocol::xproto::Window;
+use x11rb::rust_connection::RustConnection;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Active,
+    /// no input for at least the configured idle time
+    Idle,
+    /// monitors are blanked or powered down; nothing is visible
+    Off,
+}
+
+pub struct IdleMonitor {
+    root: Window,
+    dpms: bool,
+    screensaver: bool,
+    /// input idleness after which the user counts as idle; `None` disables the check
+    idle_after: Option<Duration>,
+}
+
+impl IdleMonitor {
+    /// Missing extensions are not an error; the corresponding checks are skipped.
+    pub fn new(conn: &RustConnection, root: Window, idle_after: Option<Duration>) -> crate::Result<Self> {
+        let dpms = conn.extension_information(x11rb::protocol::dpms::X11_EXTENSION_NAME)?.is_some();
+        let screensaver = conn.extension_information(x11rb::protocol::screensaver::X11_EXTENSION_NAME)?.is_some();
+        Ok(Self { root, dpms, screensaver, idle_after })
+    }
+
+    pub fn query(&self, conn: &RustConnection) -> Result<Activity, ReplyError> {
+        if self.dpms {
+            let info = conn.dpms_info()?.reply()?;
+            if info.state && info.power_level != DPMSMode::ON {
+                return Ok(Activity::Off);
+            }
+        }
+
+        if self.screensaver {
+            let info = conn.screensaver_query_info(self.root)?.reply()?;
+            if info.state == u8::from(State::ON) {
+                return Ok(Activity::Off);
+            }
+            let idle = Duration::from_millis(info.ms_since_user_input.into());
+            if self.idle_after.is_some_and(|after| idle >= after) {
+                return Ok(Activity::Idle);
+            }
+        }
+
+        Ok(Activity::Active)
+    }
+}
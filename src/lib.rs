@@ -1,3 +1,20 @@
+pub mod compositor;
+pub mod cover;
+pub mod desktop;
+pub mod error;
+pub mod format;
+pub mod idle;
+pub mod loader;
+pub mod monitor;
+pub mod output;
+pub mod pixmap;
+pub mod power;
+pub mod present;
 pub mod render;
+pub mod root;
+pub mod scene;
 pub mod shm;
+pub mod snapshot;
 pub mod texture;
+
+pub use error::{Error, Result};
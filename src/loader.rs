@@ -0,0 +1,164 @@
+//! Decodes layer images on a pool of threads, scaled down to the size they are shown at, and
+//! keeps recently used ones in memory for scenes that come back to them.
+
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::SystemTime;
+
+use image::imageops::FilterType;
+use image::RgbaImage;
+
+use crate::error::Result;
+use crate::scene::ImageSource;
+
+/// memory kept for decoded images by default
+pub const DEFAULT_CAPACITY: usize = 256 << 20;
+
+/// An image as decoded for one size. Files edited since are decoded again.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImageKey {
+    label: String,
+    modified: Option<SystemTime>,
+    size: [u32; 2],
+}
+
+impl ImageKey {
+    /// The key of `image` scaled down to fit `size`.
+    pub fn new(image: &ImageSource, size: [u32; 2]) -> Self {
+        let modified = match image {
+            ImageSource::Path(path) => std::fs::metadata(path).and_then(|m| m.modified()).ok(),
+            ImageSource::Embedded(..) => None,
+        };
+        Self { label: image.label(), modified, size }
+    }
+}
+
+struct Entry {
+    image: Arc<RgbaImage>,
+    /// `Lru::clock` when last used
+    used: u64,
+}
+
+/// Decoded images up to a total size in bytes, dropping the least recently used first.
+struct Lru {
+    entries: HashMap<ImageKey, Entry>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &ImageKey) -> Option<Arc<RgbaImage>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.used = self.clock;
+        Some(entry.image.clone())
+    }
+
+    fn insert(&mut self, key: ImageKey, image: Arc<RgbaImage>, capacity: usize) {
+        let bytes = image.as_raw().len();
+        if bytes > capacity {
+            return;
+        }
+        self.clock += 1;
+        if let Some(old) = self.entries.insert(key, Entry { image, used: self.clock }) {
+            self.bytes -= old.image.as_raw().len();
+        }
+        self.bytes += bytes;
+        while self.bytes > capacity {
+            let oldest = self.entries.iter().min_by_key(|(_, e)| e.used).map(|(k, _)| k.clone()).unwrap();
+            let entry = self.entries.remove(&oldest).unwrap();
+            self.bytes -= entry.image.as_raw().len();
+        }
+    }
+}
+
+pub struct ImageLoader {
+    cache: Mutex<Lru>,
+    /// images still held elsewhere, e.g. by a scene loaded ahead, that the lru may have dropped
+    held: Mutex<HashMap<ImageKey, Weak<RgbaImage>>>,
+    /// bytes of decoded images to keep
+    capacity: usize,
+    threads: usize,
+}
+
+impl ImageLoader {
+    /// A loader keeping up to `capacity` bytes of decoded images, decoding on as many
+    /// threads as there are cpus.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: Mutex::new(Lru { entries: HashMap::new(), bytes: 0, clock: 0 }),
+            held: Mutex::new(HashMap::new()),
+            capacity,
+            threads: std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        }
+    }
+
+    /// `image` scaled down to fit `size`, from memory if it was loaded before.
+    pub fn load(&self, image: &ImageSource, size: [u32; 2]) -> Result<Arc<RgbaImage>> {
+        self.load_key(image, ImageKey::new(image, size))
+    }
+
+    /// Loads several images at once, spread over the threads.
+    pub fn load_all(&self, requests: &[(&ImageSource, [u32; 2])]) -> Vec<Result<Arc<RgbaImage>>> {
+        let keys = requests.iter().map(|&(image, size)| ImageKey::new(image, size)).collect::<Vec<_>>();
+        // decode images that are asked for twice only once
+        let mut first = HashMap::new();
+        let unique = (0..keys.len()).filter(|&i| *first.entry(&keys[i]).or_insert(i) == i).collect::<Vec<_>>();
+
+        let results = Mutex::new(HashMap::new());
+        let next = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..self.threads.min(unique.len()) {
+                scope.spawn(|| {
+                    while let Some(&i) = unique.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let result = self.load_key(requests[i].0, keys[i].clone());
+                        results.lock().unwrap().insert(i, result);
+                    }
+                });
+            }
+        });
+
+        let results = results.into_inner().unwrap();
+        let mut all = (0..keys.len()).map(|i| {
+            let j = first[&keys[i]];
+            (i != j).then(|| match &results[&j] {
+                Ok(image) => Ok(image.clone()),
+                // errors can't be cloned; this one comes up again
+                Err(_) => self.load_key(requests[i].0, keys[i].clone()),
+            })
+        }).collect::<Vec<_>>();
+        for (i, result) in results {
+            all[i] = Some(result);
+        }
+        all.into_iter().map(Option::unwrap).collect()
+    }
+
+    fn load_key(&self, image: &ImageSource, key: ImageKey) -> Result<Arc<RgbaImage>> {
+        if let Some(decoded) = self.cache.lock().unwrap().get(&key) {
+            return Ok(decoded);
+        }
+        if let Some(decoded) = self.held.lock().unwrap().get(&key).and_then(Weak::upgrade) {
+            return Ok(decoded);
+        }
+        let decoded = Arc::new(downscale(image.load()?.into_rgba8(), key.size));
+        let mut held = self.held.lock().unwrap();
+        held.retain(|_, image| image.strong_count() > 0);
+        held.insert(key.clone(), Arc::downgrade(&decoded));
+        drop(held);
+        self.cache.lock().unwrap().insert(key, decoded.clone(), self.capacity);
+        Ok(decoded)
+    }
+}
+
+/// Shrinks each side of `img` that is larger than in `size`; layers are stretched anyway,
+/// and upscaling is left to the sampler.
+fn downscale(img: RgbaImage, size: [u32; 2]) -> RgbaImage {
+    let width = img.width().min(size[0].max(1));
+    let height = img.height().min(size[1].max(1));
+    if (width, height) == img.dimensions() {
+        return img;
+    }
+    image::imageops::resize(&img, width, height, FilterType::Lanczos3)
+}
@@ -1,159 +1,949 @@
-use x11rb::connection::Connection;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::xproto::{ConnectionExt, CloseDown, Window};
 use x11rb::wrapper::ConnectionExt as _;
-use x11rb::protocol::xproto::{
-    ConnectionExt,
-    CreateGCAux,
-    ChangeWindowAttributesAux,
-    AtomEnum,
-    CloseDown,
-    PropMode,
-};
-use x11rb::protocol::shm::ConnectionExt as ShmConnectionExt;
+use x11rb::protocol::randr::{ConnectionExt as RandrConnectionExt, NotifyMask};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use tokio::io::unix::AsyncFd;
+use tokio::task::{JoinError, JoinHandle};
+
+use xbg::compositor::CompositorWatch;
+use xbg::cover::CoverTracker;
+use xbg::desktop::{DesktopWindows, WindowGpu};
+use xbg::idle::{Activity, IdleMonitor};
+use xbg::monitor::{Monitor, Scaling};
+use xbg::output::{Frame, ImageFile, OutputBackend, RawStream};
+use xbg::power::{PowerConfig, PowerPolicy};
+use xbg::present::FrameClock;
+use xbg::render::{OutputFormat, Renderer, RendererBuilder};
+use xbg::root::RootWindow;
+use xbg::scene::{Placement, Scene};
+use xbg::texture::TextureCache;
+
+/// default frame rate when frames are sent with PutImage
+const REMOTE_FPS: f32 = 10.0;
+
+/// how often power, thermal, display and idle state are re-checked
+const STATE_POLL: Duration = Duration::from_secs(1);
+
+#[derive(Parser)]
+#[command(about = "animated wallpaper for X11", args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    source: SceneArgs,
+
+    /// exit after publishing a static scene, like `feh --bg-fill`.
+    /// otherwise xbg sleeps until SIGHUP reloads the scene.
+    #[arg(long)]
+    exit: bool,
+
+    /// on SIGINT or SIGTERM, leave the last frame as the wallpaper instead of clearing it
+    #[arg(long)]
+    keep_on_exit: bool,
+
+    /// draw into desktop windows below all others instead of the root window, for desktops
+    /// that paint over the root window. frames stay on the gpu.
+    #[arg(long, conflicts_with_all = ["exit", "keep_on_exit"])]
+    desktop_window: bool,
+
+    /// X display to draw on, on all of its screens; may be repeated [default: $DISPLAY]
+    #[arg(long = "display", value_name = "DISPLAY")]
+    displays: Vec<String>,
+
+    /// scale factor for layers sized in logical pixels, like 2 for all monitors or DP-1=1.5
+    /// for one; may be repeated [default: from the monitor's dpi, or Xft.dpi]
+    #[arg(long = "scale", value_name = "[MONITOR=]FACTOR", value_parser = parse_scale)]
+    scales: Vec<(Option<String>, f32)>,
+
+    /// memory for decoded images kept for when a scene comes back to them, in MiB
+    #[arg(long, default_value_t = xbg::loader::DEFAULT_CAPACITY >> 20)]
+    image_cache: usize,
+
+    /// slow animation down after this many minutes without user input
+    #[arg(long)]
+    idle_minutes: Option<u64>,
+
+    /// frame rate while the user is idle; 0 pauses animation
+    #[arg(long, default_value_t = 5.0)]
+    idle_fps: f32,
+
+    /// draw on every Nth vblank of the fastest monitor at most
+    #[arg(long, default_value_t = 1)]
+    refresh_divisor: u64,
+
+    #[command(flatten)]
+    power: PowerArgs,
+}
 
-#[tokio::main]
-async
-fn main() {
+#[derive(clap::Subcommand)]
+enum Command {
+    /// render a single frame to an image file, without an X server
+    Render(RenderArgs),
+    /// render frames continuously as raw BGRX pixels (ffmpeg's bgr0), without an X server
+    Stream(StreamArgs),
+}
+
+#[derive(clap::Args)]
+struct SceneArgs {
+    /// scene description (toml)
+    #[arg(long, conflicts_with = "image")]
+    scene: Option<PathBuf>,
+
+    /// use a single static image as wallpaper
+    #[arg(long)]
+    image: Option<PathBuf>,
+
+    /// spread the image over all monitors instead of showing it on each
+    #[arg(long, requires = "image")]
+    span: bool,
+
+    /// gap between neighbouring panels in mm for spanned images, e.g. 20x15
+    /// [default: the scene's, or none]
+    #[arg(long, value_parser = parse_bezel)]
+    bezel: Option<[f32; 2]>,
+}
 
-    let (conn, screen_num) = x11rb::connect(None).unwrap();
-    let screen = &conn.setup().roots[screen_num];
-    let root = screen.root;
-    let depth = screen.root_depth;
+/// What to render when there is no X server to ask.
+#[derive(clap::Args)]
+struct FrameArgs {
+    #[command(flatten)]
+    source: SceneArgs,
 
-    // check shm
-    if !conn.query_extension(x11rb::protocol::shm::X11_EXTENSION_NAME.as_bytes()).unwrap().reply().unwrap().present {
-        panic!("shm extension is not supported");
+    /// frame size, e.g. 3840x1080
+    #[arg(long, value_parser = parse_size)]
+    size: [u16; 2],
+
+    /// monitor rectangles like 1920x1080+1920+0, separated by commas [default: the whole frame]
+    #[arg(long, value_parser = parse_monitor, value_delimiter = ',')]
+    monitors: Vec<Monitor>,
+
+    /// scale factor of all monitors for layers sized in logical pixels
+    #[arg(long, default_value_t = 1.0, value_parser = parse_factor)]
+    scale: f32,
+}
+
+impl FrameArgs {
+    async fn renderer(&self) -> anyhow::Result<Renderer<'static>> {
+        let mut monitors = if self.monitors.is_empty() {
+            vec![Monitor::from([0, 0, self.size[0], self.size[1]])]
+        } else {
+            self.monitors.clone()
+        };
+        for m in &mut monitors {
+            m.scale = Some(self.scale);
+        }
+        let scene = self.source.load()?;
+        Ok(Renderer::new(self.size, &monitors, &scene, OutputFormat::Bgra8).await?)
     }
+}
+
+#[derive(clap::Args)]
+struct RenderArgs {
+    #[command(flatten)]
+    frame: FrameArgs,
+
+    /// animation time in seconds
+    #[arg(long, default_value = "0", value_parser = parse_time)]
+    time: Duration,
+
+    /// output file; the format follows the extension (png, ppm)
+    #[arg(short, long)]
+    output: PathBuf,
+}
 
-    if !conn.query_extension(x11rb::protocol::randr::X11_EXTENSION_NAME.as_bytes()).unwrap().reply().unwrap().present {
-        panic!("randr extension is not supported");
+#[derive(clap::Args)]
+struct StreamArgs {
+    #[command(flatten)]
+    frame: FrameArgs,
+
+    #[arg(long, default_value_t = 30.0, value_parser = parse_stream_fps)]
+    fps: f32,
+
+    /// stop after this many frames
+    #[arg(long)]
+    frames: Option<u64>,
+
+    /// file or FIFO to write to; `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    output: PathBuf,
+}
+
+fn parse_size(s: &str) -> Result<[u16; 2], String> {
+    let (w, h) = s.split_once('x').ok_or("expected WIDTHxHEIGHT")?;
+    let size = [
+        w.parse().map_err(|e| format!("bad width: {}", e))?,
+        h.parse().map_err(|e| format!("bad height: {}", e))?,
+    ];
+    if size.contains(&0) {
+        return Err("size must not be zero".into());
     }
+    Ok(size)
+}
 
-    conn.set_close_down_mode(CloseDown::RETAIN_PERMANENT).unwrap();
-    conn.sync().unwrap();
+fn parse_time(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|e| format!("bad time: {}", e))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| "time must be a non-negative number of seconds".to_string())
+}
 
-    // let pixmap = conn.generate_id().unwrap();
-    // conn.create_pixmap(depth, pixmap, root, screen.width_in_pixels, screen.height_in_pixels).unwrap();
+fn parse_stream_fps(s: &str) -> Result<f32, String> {
+    let fps: f32 = s.parse().map_err(|e| format!("bad frame rate: {}", e))?;
+    if !(fps > 0.0 && fps.is_finite()) {
+        return Err("frame rate must be positive".into());
+    }
+    Ok(fps)
+}
 
-    let pm = xbg::shm::ShmPixmap::new(&conn, root, screen.width_in_pixels, screen.height_in_pixels).unwrap();
+fn parse_factor(s: &str) -> Result<f32, String> {
+    let factor: f32 = s.parse().map_err(|e| format!("bad factor: {}", e))?;
+    if !(factor > 0.0 && factor.is_finite()) {
+        return Err("factor must be positive".into());
+    }
+    Ok(factor)
+}
 
-    println!("pixmap: 0x{:08x}", pm.pixmap);
+fn parse_bezel(s: &str) -> Result<[f32; 2], String> {
+    let (h, v) = s.split_once('x').ok_or("expected HORIZONTALxVERTICAL")?;
+    let bezel: [f32; 2] = [
+        h.parse().map_err(|e| format!("bad horizontal gap: {}", e))?,
+        v.parse().map_err(|e| format!("bad vertical gap: {}", e))?,
+    ];
+    if !bezel.iter().all(|gap| *gap >= 0.0 && gap.is_finite()) {
+        return Err("gaps must be finite and not negative".into());
+    }
+    Ok(bezel)
+}
 
-    let gc = conn.generate_id().unwrap();
-    println!("gc: 0x{:08x}", gc);
-    let gc_aux = CreateGCAux::new()
-        .background(screen.white_pixel)
-        .foreground(0xffff0000);
-    conn.create_gc(gc, root, &gc_aux).unwrap();
+fn parse_scale(s: &str) -> Result<(Option<String>, f32), String> {
+    let (name, factor) = match s.split_once('=') {
+        Some((name, factor)) => (Some(name.to_string()), factor),
+        None => (None, s),
+    };
+    Ok((name, parse_factor(factor)?))
+}
 
-    // let mut buf = [0u8; 640 * 480 * 4];
+fn parse_monitor(s: &str) -> Result<Monitor, String> {
+    let mut parts = s.split('+');
+    let [width, height] = parse_size(parts.next().unwrap_or_default())?;
+    let (Some(x), Some(y), None) = (parts.next(), parts.next(), parts.next()) else {
+        return Err("expected WIDTHxHEIGHT+X+Y".into());
+    };
+    Ok(Monitor {
+        x: x.parse().map_err(|e| format!("bad x: {}", e))?,
+        y: y.parse().map_err(|e| format!("bad y: {}", e))?,
+        width,
+        height,
+        ..Default::default()
+    })
+}
 
-    let prop_root = conn.intern_atom(false, b"_XROOTPMAP_ID").unwrap().reply().unwrap().atom;
-    let prop_esetroot = conn.intern_atom(false, b"ESETROOT_PMAP_ID").unwrap().reply().unwrap().atom;
+/// Renders one frame offscreen and writes it to a file.
+async fn render_to_file(args: &RenderArgs) -> anyhow::Result<()> {
+    let mut rnd = args.frame.renderer().await?;
+    let mut output = ImageFile::new(&args.output);
+    show_frame(&mut rnd, &mut output, args.time).await?;
 
-    conn.change_property32(
-        PropMode::REPLACE,
-        root,
-        prop_root,
-        AtomEnum::PIXMAP,
-        &[pm.pixmap],
-    ).unwrap();
+    println!("wrote {}", args.output.display());
+    Ok(())
+}
 
-    conn.change_property32(
-        PropMode::REPLACE,
-        root,
-        prop_esetroot,
-        AtomEnum::PIXMAP,
-        &[pm.pixmap]
-    ).unwrap();
+/// Renders frames offscreen at a fixed rate and writes them out raw.
+async fn stream_frames(args: &StreamArgs) -> anyhow::Result<()> {
+    let mut rnd = args.frame.renderer().await?;
+    let mut output: Box<dyn OutputBackend> = if args.output.as_os_str() == "-" {
+        Box::new(RawStream::stdout())
+    } else {
+        Box::new(RawStream::open(&args.output)?)
+    };
+
+    let period = Duration::from_secs_f32(1.0 / args.fps);
+    let mut timer = tokio::time::interval(period);
+    for n in 0..args.frames.unwrap_or(u64::MAX) {
+        timer.tick().await;
+        match show_frame(&mut rnd, output.as_mut(), period * n as u32).await {
+            Ok(()) => {}
+            // the reader went away
+            Err(xbg::Error::Io { source, .. }) if source.kind() == std::io::ErrorKind::BrokenPipe => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
 
-    conn.change_window_attributes(root, &ChangeWindowAttributesAux::new().background_pixmap(pm.pixmap)).unwrap();
+#[derive(clap::Args)]
+struct PowerArgs {
+    /// frame rate on AC power [default: 60, or 10 without shared memory]
+    #[arg(long)]
+    fps: Option<f32>,
 
-    let monitors = x11rb::protocol::randr::get_monitors(&conn, root, false).unwrap().reply().unwrap().monitors.iter().map(|m| {
-        [m.x.try_into().unwrap(), m.y.try_into().unwrap(), m.width.into(), m.height.into()]
-    }).collect::<Vec<_>>();
-    println!("monitors: {:?}", monitors);
+    /// frame rate on battery
+    #[arg(long, default_value_t = PowerConfig::default().battery_fps)]
+    battery_fps: f32,
 
-    let mut i = 1;
+    /// frame rate on low battery; 0 pauses animation
+    #[arg(long, default_value_t = PowerConfig::default().low_battery_fps)]
+    low_battery_fps: f32,
 
-    let mut rnd = xbg::render::Renderer::new(
-        [screen.width_in_pixels.into(), screen.height_in_pixels.into()],
-        &monitors
-        ).await;
+    /// charge in percent below which the battery counts as low
+    #[arg(long, default_value_t = PowerConfig::default().low_battery_percent)]
+    low_battery_percent: u8,
+
+    /// frame rate while the machine is hot; 0 pauses animation
+    #[arg(long, default_value_t = PowerConfig::default().hot_fps)]
+    hot_fps: f32,
+
+    /// temperature in degrees celsius from which the machine counts as hot
+    #[arg(long, default_value_t = PowerConfig::default().hot_celsius)]
+    hot_celsius: f32,
+
+    /// where to read power supply and thermal state from
+    #[arg(long, default_value = "/sys", hide = true)]
+    sysfs: PathBuf,
+}
 
-    println!("start");
+impl PowerArgs {
+    /// `remote` lowers the default frame rate, for servers that frames have to be sent to
+    /// over the wire.
+    fn policy(&self, remote: bool) -> PowerPolicy {
+        let default_fps = if remote { REMOTE_FPS } else { PowerConfig::default().fps };
+        PowerPolicy::new(&self.sysfs, PowerConfig {
+            fps: self.fps.unwrap_or(default_fps),
+            battery_fps: self.battery_fps,
+            low_battery_fps: self.low_battery_fps,
+            low_battery_percent: self.low_battery_percent,
+            hot_fps: self.hot_fps,
+            hot_celsius: self.hot_celsius,
+        })
+    }
+}
 
+/// Frame rate to use right now; 0 pauses animation.
+fn target_fps(args: &Args, policy: &PowerPolicy, idle: &IdleMonitor, conn: &RustConnection) -> f32 {
+    let fps = policy.fps();
+    match idle.query(conn).unwrap() {
+        Activity::Active => fps,
+        Activity::Idle => fps.min(args.idle_fps),
+        Activity::Off => 0.0,
+    }
+}
 
-    conn.flush().unwrap();
+/// Decides when the next frame is drawn.
+struct Pacer {
+    /// vblank notifications; `None` if the server lacks the Present extension
+    clock: Option<FrameClock>,
+    /// fastest monitor refresh rate in Hz
+    refresh: f32,
+    /// draw every `divisor`th vblank at most
+    divisor: u64,
+    /// fixed timer used instead of `clock` when set
+    timer: Option<tokio::time::Interval>,
+}
 
-    let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(16));
+impl Pacer {
+    fn new(clock: Option<FrameClock>, refresh: f32, divisor: u64) -> Self {
+        Self { clock, refresh, divisor, timer: None }
+    }
 
-    let start = std::time::Instant::now();
+    /// Paces frames at `fps` at most; 0 means paused, in which case we still wake up every
+    /// `STATE_POLL` to notice when to resume.
+    fn set_fps(&mut self, fps: f32) {
+        if fps <= 0.0 {
+            self.timer = Some(tokio::time::interval(STATE_POLL));
+            return;
+        }
+        let divisor = xbg::present::divisor_for(self.refresh, fps, self.divisor);
+        if let Some(clock) = &mut self.clock {
+            clock.set_divisor(divisor);
+            self.timer = None;
+        } else {
+            let period = Duration::from_secs_f32(divisor as f32 / self.refresh);
+            self.timer = Some(tokio::time::interval(period));
+        }
+    }
 
-    loop {
-        let mut t = std::time::Instant::now();
-        rnd.render(
-            start.elapsed(),
-            |buf| {
-                println!("render {}us", t.elapsed().as_micros()); t = std::time::Instant::now();
-                println!("buf: {}", buf.len());
-                pm.shmseg.as_slice().copy_from_slice(&buf);
-            }
-        ).await.unwrap();
+    /// Waits until the next frame is due, feeding X events to the screens meanwhile.
+    /// The clock runs on the first display.
+    async fn wait(&mut self, displays: &[Display], screens: &mut [Screen<'_>]) {
+        if let Some(timer) = &mut self.timer {
+            timer.tick().await;
+            return;
+        }
+        let clock = self.clock.as_mut().unwrap();
+        let conn = &displays[0].conn;
+        clock.request(conn).unwrap();
         conn.flush().unwrap();
-        println!("copy {}us", t.elapsed().as_micros()); let t = std::time::Instant::now();
 
-        // render; for non-compositor
-        // conn.copy_area(pm.pixmap, root, gc, 0, 0, 0, 0, 500, 400).unwrap();
-        conn.shm_put_image(
-            //pm.pixmap,
-            root,
-            gc,
-            screen.width_in_pixels,
-            screen.height_in_pixels,
-            0,
-            0,
-            screen.width_in_pixels,
-            screen.height_in_pixels,
-            0,
-            0,
-            24,
-            x11rb::protocol::xproto::ImageFormat::Z_PIXMAP.into(),
-            true,
-            pm.shmseg.seg,
-            0
-        ).unwrap();
+        // a powered down display stops counting vblanks; don't wait forever
+        let deadline = tokio::time::sleep(STATE_POLL);
+        tokio::pin!(deadline);
+        loop {
+            if poll_events(displays, screens, Some(clock)) {
+                return;
+            }
+            tokio::select! {
+                _ = readable(displays) => {}
+                _ = &mut deadline => {
+                    clock.reset();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// State kept up to date from X events.
+struct Watch {
+    cover: CoverTracker,
+    compositor: CompositorWatch,
+    /// set when a compositor started or stopped
+    compositor_changed: bool,
+    /// set when monitors were added, removed, resized or rotated
+    layout_changed: bool,
+    /// set when parts of our desktop windows need to be redrawn
+    exposed: bool,
+}
+
+impl Watch {
+    fn handle_event(&mut self, conn: &RustConnection, output: &Target, event: &Event) {
+        self.cover.handle_event(conn, event).unwrap();
+        if self.compositor.handle_event(event) {
+            self.compositor_changed = true;
+        }
+        if matches!(event, Event::RandrScreenChangeNotify(_) | Event::RandrNotify(_)) {
+            self.layout_changed = true;
+        }
+        if let (Event::Expose(e), Target::Desktop(desktop)) = (event, output) {
+            self.exposed |= desktop.contains(e.window);
+        }
+    }
+
+    /// True if events were seen that haven't been followed yet.
+    fn is_pending(&self) -> bool {
+        self.compositor_changed || self.layout_changed || self.exposed
+    }
+
+    /// Tells `output` whether a compositor runs, if that changed since the last call.
+    fn follow_compositor(&mut self, output: &mut Target) {
+        if !std::mem::take(&mut self.compositor_changed) {
+            return;
+        }
+        println!("compositor: {}", self.compositor.is_active());
+        if let Target::Root(root) = output {
+            root.set_compositing(self.compositor.is_active());
+        }
+    }
+}
+
+impl Args {
+    fn scaling(&self) -> Scaling {
+        let mut scaling = Scaling::default();
+        for (name, factor) in &self.scales {
+            match name {
+                Some(name) => scaling.monitors.push((name.clone(), *factor)),
+                None => scaling.default = Some(*factor),
+            }
+        }
+        scaling
+    }
+}
+
+impl SceneArgs {
+    fn load(&self) -> xbg::Result<Scene> {
+        let mut scene = if let Some(path) = &self.scene {
+            Scene::load(path)?
+        } else if let Some(path) = &self.image {
+            let placement = if self.span { Placement::Span } else { Placement::Monitor };
+            Scene::from_image(path.clone(), placement)
+        } else {
+            Scene::demo()
+        };
+        if let Some(bezel) = self.bezel {
+            scene.bezel = bezel;
+        }
+        Ok(scene)
+    }
+}
+
+/// Renders the frame at time `t` synchronously and shows all of it.
+async fn show_frame(rnd: &mut Renderer<'_>, output: &mut dyn OutputBackend, t: Duration) -> xbg::Result<()> {
+    let (rows, format) = (rnd.row_layout(), rnd.format());
+    let damage = [[0, 0, rnd.get_width() as u16, rnd.get_height() as u16]];
+    rnd.render(t, |buf| {
+        output.present(&Frame { data: &buf, rows, format, damage: &damage })
+    }).await?
+}
+
+/// Where the daemon shows frames.
+enum Target<'c> {
+    Root(RootWindow<'c>),
+    Desktop(Box<DesktopWindows<'c>>),
+}
+
+impl Target<'_> {
+    async fn renderer(
+        &self,
+        size: [u16; 2],
+        monitors: &[Monitor],
+        scene: &Scene,
+        cache: &mut TextureCache,
+    ) -> xbg::Result<Renderer<'static>> {
+        match self {
+            Target::Root(root) => {
+                RendererBuilder::new(size)
+                    .monitors(monitors)
+                    .format(root.pixel_format().output_format())
+                    .cached_scene(scene, cache)?
+                    .build()
+                    .await
+            }
+            Target::Desktop(desktop) => desktop.renderer(size, monitors, scene, cache).await,
+        }
+    }
+
+    /// Renders the frame at time `t` and shows it on every monitor.
+    async fn show_frame(&mut self, rnd: &mut Renderer<'_>, monitors: &[Monitor], t: Duration) -> xbg::Result<()> {
+        match self {
+            Target::Root(root) => show_frame(rnd, root, t).await,
+            Target::Desktop(desktop) => desktop.draw(rnd, t, &vec![true; monitors.len()]),
+        }
+    }
+
+    fn configure(&mut self, size: [u16; 2], monitors: &[Monitor]) -> xbg::Result<()> {
+        match self {
+            Target::Root(root) => root.configure(size, &rects(size, monitors)),
+            Target::Desktop(desktop) => desktop.configure(monitors),
+        }
+    }
+
+    fn shutdown(self, keep: bool) -> xbg::Result<()> {
+        match self {
+            Target::Root(root) => {
+                root.shutdown(keep)?;
+                println!("{}", if keep { "published wallpaper" } else { "wallpaper cleared" });
+                Ok(())
+            }
+            Target::Desktop(desktop) => desktop.close(),
+        }
+    }
+}
+
+/// The parts of `monitors` inside a root window of `size`.
+fn rects(size: [u16; 2], monitors: &[Monitor]) -> Vec<[u16; 4]> {
+    monitors.iter().map(|m| m.rect(size)).collect()
+}
+
+/// A connection to an X server, whose screens all get a wallpaper.
+struct Display {
+    /// as given to `--display`; `None` for `$DISPLAY`
+    name: Option<String>,
+    conn: RustConnection,
+    /// the screen named by the display name
+    preferred_screen: usize,
+    xfd: AsyncFd<RawFd>,
+}
+
+impl Display {
+    fn connect(name: Option<&str>) -> anyhow::Result<Self> {
+        let (conn, preferred_screen) = x11rb::connect(name).map_err(xbg::Error::from)?;
+        if conn.extension_information(x11rb::protocol::randr::X11_EXTENSION_NAME)?.is_none() {
+            return Err(xbg::Error::MissingExtension(x11rb::protocol::randr::X11_EXTENSION_NAME).into());
+        }
+        let xfd = AsyncFd::new(conn.stream().as_raw_fd())?;
+        Ok(Self { name: name.map(String::from), conn, preferred_screen, xfd })
+    }
+
+    /// Screen numbers, the preferred one first.
+    fn screens(&self) -> Vec<usize> {
+        let others = (0..self.conn.setup().roots.len()).filter(|&n| n != self.preferred_screen);
+        std::iter::once(self.preferred_screen).chain(others).collect()
+    }
+}
 
-        println!("draw {}us", t.elapsed().as_micros()); let t = std::time::Instant::now();
+/// Feeds pending events of every display to its screens. Returns true if one of them was the
+/// vblank `clock` on the first display waits for.
+fn poll_events(displays: &[Display], screens: &mut [Screen<'_>], mut clock: Option<&mut FrameClock>) -> bool {
+    let mut vblank = false;
+    for (i, display) in displays.iter().enumerate() {
+        while let Some(event) = display.conn.poll_for_event().unwrap() {
+            for screen in screens.iter_mut().filter(|s| s.display == i) {
+                screen.watch.handle_event(screen.conn, &screen.output, &event);
+            }
+            if let (0, Some(clock)) = (i, clock.as_deref_mut()) {
+                vblank |= clock.handle_event(&event);
+            }
+        }
+    }
+    vblank
+}
 
-        // notify compositor
-        conn.change_property32(
-            PropMode::REPLACE,
+/// Waits until any of the displays has something to read.
+async fn readable(displays: &[Display]) {
+    std::future::poll_fn(|cx| {
+        for display in displays {
+            if let Poll::Ready(guard) = display.xfd.poll_read_ready(cx) {
+                guard.unwrap().clear_ready();
+                return Poll::Ready(());
+            }
+        }
+        Poll::Pending
+    }).await
+}
+
+/// Decoded images of a scene, kept until its renderers hold them as textures.
+type Preloaded = Vec<xbg::Result<Arc<image::RgbaImage>>>;
+
+/// Decodes the images of `scene` for every screen on a blocking thread, so that the current
+/// scene keeps running meanwhile. Failures come up again when the renderers are built.
+fn preload(screens: &[Screen<'_>], cache: &TextureCache, scene: &Scene) -> JoinHandle<Preloaded> {
+    let requests = screens.iter().flat_map(|screen| {
+        RendererBuilder::new(screen.size)
+            .monitors(&screen.monitors)
+            .image_requests(scene)
+            .into_iter()
+            .map(|(image, size)| (image.clone(), size))
+            .collect::<Vec<_>>()
+    }).collect::<Vec<_>>();
+    let loader = cache.loader().clone();
+    tokio::task::spawn_blocking(move || {
+        loader.load_all(&requests.iter().map(|(image, size)| (image, *size)).collect::<Vec<_>>())
+    })
+}
+
+/// Waits for the scene being loaded, if any.
+async fn loaded(reload: &mut Option<(JoinHandle<Preloaded>, Scene)>) -> Result<Preloaded, JoinError> {
+    match reload {
+        Some((images, _)) => images.await,
+        None => std::future::pending().await,
+    }
+}
+
+/// One X screen and its wallpaper.
+struct Screen<'c> {
+    /// index into the displays
+    display: usize,
+    conn: &'c RustConnection,
+    root: Window,
+    output: Target<'c>,
+    watch: Watch,
+    size: [u16; 2],
+    monitors: Vec<Monitor>,
+    scaling: Scaling,
+}
+
+impl<'c> Screen<'c> {
+    /// Takes over screen `num` of `display`. Desktop windows are drawn with `gpu` if given.
+    async fn new(
+        index: usize,
+        display: &'c Display,
+        num: usize,
+        args: &Args,
+        gpu: Option<WindowGpu>,
+    ) -> anyhow::Result<Screen<'c>> {
+        let conn = &display.conn;
+        let root = conn.setup().roots[num].root;
+
+        conn.randr_select_input(
             root,
-            prop_root,
-            AtomEnum::PIXMAP,
-            &[pm.pixmap],
+            NotifyMask::SCREEN_CHANGE | NotifyMask::CRTC_CHANGE | NotifyMask::OUTPUT_CHANGE,
         ).unwrap();
 
-        conn.flush().unwrap();
+        let scaling = args.scaling();
+        let (size, monitors) = xbg::monitor::query(conn, root, &scaling)?;
+        println!("screen {}: monitors: {:?}", num, monitors);
+
+        let mut output = if args.desktop_window {
+            Target::Desktop(Box::new(DesktopWindows::new(conn, display.name.as_deref(), num, &monitors, gpu).await?))
+        } else {
+            let root = RootWindow::new(conn, num, size)?;
+            println!(
+                "screen {}: format: {:?}, frames via {}",
+                num, root.pixel_format(), if root.is_shm() { "shared memory" } else { "PutImage" },
+            );
+            Target::Root(root)
+        };
+
+        let mut watch = Watch {
+            cover: CoverTracker::new(conn, root, &rects(size, &monitors)).unwrap(),
+            compositor: CompositorWatch::new(conn, num)?,
+            compositor_changed: true,
+            layout_changed: false,
+            exposed: false,
+        };
+        watch.follow_compositor(&mut output);
+
+        Ok(Self { display: index, conn, root, output, watch, size, monitors, scaling })
+    }
 
-        println!("notify {}us", t.elapsed().as_micros());
+    /// True if frames have to be sent over the wire.
+    fn is_remote(&self) -> bool {
+        match &self.output {
+            Target::Root(root) => !root.is_shm(),
+            // frames for desktop windows never leave the gpu
+            Target::Desktop(_) => false,
+        }
+    }
 
-        // std::thread::sleep(std::time::Duration::from_millis(500));
-        interval.tick().await;
+    /// Follows compositor and layout changes seen since the last call. After a layout change,
+    /// the first frame at `t` in the new layout is shown.
+    async fn follow_changes(&mut self, rnd: &mut Renderer<'_>, t: Duration) {
+        self.watch.follow_compositor(&mut self.output);
+        if !std::mem::take(&mut self.watch.layout_changed) {
+            return;
+        }
+        let (size, monitors) = xbg::monitor::query(self.conn, self.root, &self.scaling).unwrap();
+        println!("layout changed: {:?} {:?}", size, monitors);
+
+        rnd.resize(size, &monitors);
+        self.watch.cover.set_monitors(&rects(size, &monitors));
+        self.output.configure(size, &monitors).unwrap();
+        self.output.show_frame(rnd, &monitors, t).await.unwrap();
+        (self.size, self.monitors) = (size, monitors);
+    }
 
-        // if i > 5000 {
-        //     break;
-        // }
-        i += 1;
+    /// Draws the frame at `t` on monitors that aren't covered, unless `drawing` is off. Frames
+    /// read back from the gpu are shown as they become ready.
+    fn draw(&mut self, rnd: &mut Renderer<'_>, t: Duration, drawing: bool) -> xbg::Result<()> {
+        let active = self.watch.cover.covered(self.conn).unwrap().iter().map(|&c| !c).collect::<Vec<_>>();
+        rnd.set_active(&active);
+
+        // skip rendering while everything is hidden behind fullscreen windows,
+        // while the displays are off, or while the power policy asks us to stop
+        let drawing = drawing && !rnd.is_paused();
+        match &mut self.output {
+            Target::Root(output) => {
+                if drawing {
+                    // the gpu works on this frame while an earlier one is uploaded below
+                    rnd.submit(t);
+                }
+
+                let (rows, output_format) = (rnd.row_layout(), rnd.format());
+                let damage = xbg::output::damage(self.size, &rects(self.size, &self.monitors), &active);
+                if let Some(result) = rnd.try_read(|buf| {
+                    output.present(&Frame { data: &buf, rows, format: output_format, damage: &damage })
+                })? {
+                    result?;
+                }
+            }
+            Target::Desktop(desktop) => {
+                // exposed windows are redrawn even while paused
+                if std::mem::take(&mut self.watch.exposed) | drawing {
+                    desktop.draw(rnd, t, &active)?;
+                }
+            }
+        }
+        Ok(())
     }
+}
 
-    // conn.get_property(true, root, prop_root, AtomEnum::ANY, 0, 1).unwrap().reply().unwrap();
-    // conn.get_property(true, root, prop_esetroot, AtomEnum::ANY, 0, 1).unwrap().reply().unwrap();
+#[tokio::main]
+async
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    match &args.command {
+        Some(Command::Render(render)) => return render_to_file(render).await,
+        Some(Command::Stream(stream)) => return stream_frames(stream).await,
+        None => {}
+    }
 
-    // std::thread::sleep(std::time::Duration::from_millis(100000));
+    let displays = if args.displays.is_empty() {
+        vec![Display::connect(None)?]
+    } else {
+        args.displays.iter().map(|name| Display::connect(Some(name))).collect::<anyhow::Result<_>>()?
+    };
+
+    if !args.desktop_window {
+        // the wallpaper pixmaps have to outlive us
+        for display in &displays {
+            display.conn.set_close_down_mode(CloseDown::RETAIN_PERMANENT).unwrap();
+            display.conn.sync().unwrap();
+        }
+    }
 
-    // unreachable but whatever
-    // conn.free_pixmap(pixmap).unwrap();
-    // conn.free_gc(gc).unwrap();
+    // desktop windows need a device that can draw to them, so the first screen picks it
+    let mut gpu: Option<WindowGpu> = None;
+    let mut screens = Vec::new();
+    for (i, display) in displays.iter().enumerate() {
+        for num in display.screens() {
+            let screen = Screen::new(i, display, num, &args, gpu.clone()).await?;
+            if let Target::Desktop(desktop) = &screen.output {
+                gpu.get_or_insert_with(|| desktop.gpu().clone());
+            }
+            screens.push(screen);
+        }
+    }
+    // every screen renders on one device, so that images are loaded once
+    let (device, queue) = match &gpu {
+        Some(gpu) => (gpu.device().0.clone(), gpu.device().1.clone()),
+        None => xbg::render::request_device(false).await?,
+    };
+    let mut cache = TextureCache::new(device, queue, args.image_cache << 20);
+
+    // idle time and vblanks come from the preferred screen of the first display
+    let (conn, root) = (&displays[0].conn, screens[0].root);
+
+    let policy = args.power.policy(screens.iter().any(Screen::is_remote));
+    let idle = IdleMonitor::new(
+        conn,
+        root,
+        args.idle_minutes.map(|m| Duration::from_secs(m * 60)),
+    ).unwrap();
 
+    // assume the common 60Hz if randr can't tell
+    let refresh = xbg::present::refresh_rate(conn, root).unwrap().unwrap_or(60.0);
+    let clock = FrameClock::new(conn, root, args.refresh_divisor).unwrap();
+    println!("refresh: {}Hz, present: {}", refresh, clock.is_some());
+    let mut pacer = Pacer::new(clock, refresh, args.refresh_divisor);
+
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut scene = args.source.load()?;
+    let mut preloaded = preload(&screens, &cache, &scene).await?;
+
+    'scene: loop {
+        let mut rnds = Vec::new();
+        for screen in &screens {
+            rnds.push(screen.output.renderer(screen.size, &screen.monitors, &scene, &mut cache).await?);
+        }
+        preloaded.clear();
+        // the next scene, decoding while this one keeps running
+        let mut reload = None;
+
+        println!("start");
+
+        for display in &displays {
+            display.conn.flush().unwrap();
+        }
+
+        if rnds.iter().all(|rnd| rnd.is_static()) {
+            // nothing moves; one frame is enough
+            println!("static scene");
+            for (screen, rnd) in screens.iter_mut().zip(&mut rnds) {
+                screen.output.show_frame(rnd, &screen.monitors, Duration::ZERO).await?;
+            }
+
+            if args.exit {
+                // publishes the frames as plain pixmaps
+                for screen in screens {
+                    screen.output.shutdown(true)?;
+                }
+                return Ok(());
+            }
+
+            // sleep until the scene is reloaded, redrawing only when the monitors change or
+            // the desktop windows are exposed
+            loop {
+                poll_events(&displays, &mut screens, pacer.clock.as_mut());
+                for (screen, rnd) in screens.iter_mut().zip(&mut rnds) {
+                    screen.follow_changes(rnd, Duration::ZERO).await;
+                    if std::mem::take(&mut screen.watch.exposed) {
+                        if let Target::Desktop(desktop) = &mut screen.output {
+                            desktop.draw(rnd, Duration::ZERO, &vec![true; screen.monitors.len()])?;
+                        }
+                    }
+                }
+                for display in &displays {
+                    display.conn.flush().unwrap();
+                }
+                // the round-trips above may have queued events inside x11rb, which
+                // `readable` doesn't see
+                poll_events(&displays, &mut screens, pacer.clock.as_mut());
+                if screens.iter().any(|s| s.watch.is_pending()) {
+                    continue;
+                }
+                tokio::select! {
+                    _ = readable(&displays) => {}
+                    _ = hangup.recv() => {
+                        println!("reloading scene");
+                        let next = args.source.load()?;
+                        reload = Some((preload(&screens, &cache, &next), next));
+                    }
+                    images = loaded(&mut reload) => {
+                        preloaded = images?;
+                        scene = reload.take().unwrap().1;
+                        break;
+                    }
+                    _ = interrupt.recv() => break 'scene,
+                    _ = terminate.recv() => break 'scene,
+                }
+            }
+            continue;
+        }
+
+        let mut fps = target_fps(&args, &policy, &idle, conn);
+        pacer.set_fps(fps);
+        let mut state_checked = Instant::now();
+        let mut dropped = vec![0; rnds.len()];
+        println!("fps: {}", fps);
+
+        let start = Instant::now();
+
+        loop {
+            if state_checked.elapsed() >= STATE_POLL {
+                state_checked = Instant::now();
+                let new_fps = target_fps(&args, &policy, &idle, conn);
+                for screen in &mut screens {
+                    if screen.watch.compositor.poll(screen.conn).unwrap() {
+                        screen.watch.compositor_changed = true;
+                    }
+                }
+                // only worth a line when a screen can't keep up
+                for (n, (rnd, dropped)) in rnds.iter().zip(&mut dropped).enumerate() {
+                    let stats = rnd.stats();
+                    if stats.dropped > *dropped {
+                        println!(
+                            "screen {}: frames: {}, dropped: {}, latency: {}us, throughput: {:.1}fps",
+                            n, stats.frames, stats.dropped, stats.latency.as_micros(), stats.throughput,
+                        );
+                    }
+                    *dropped = stats.dropped;
+                }
+                if new_fps != fps {
+                    println!("fps: {}", new_fps);
+                    fps = new_fps;
+                    pacer.set_fps(fps);
+                }
+            }
+
+            // a vblank asked for by a `wait` that was cut short arrives here
+            poll_events(&displays, &mut screens, pacer.clock.as_mut());
+            let t = start.elapsed();
+            for (screen, rnd) in screens.iter_mut().zip(&mut rnds) {
+                screen.follow_changes(rnd, t).await;
+                screen.draw(rnd, t, fps > 0.0)?;
+            }
+
+            tokio::select! {
+                _ = pacer.wait(&displays, &mut screens) => {}
+                _ = hangup.recv() => {
+                    println!("reloading scene");
+                    let next = args.source.load()?;
+                    reload = Some((preload(&screens, &cache, &next), next));
+                }
+                images = loaded(&mut reload) => {
+                    preloaded = images?;
+                    scene = reload.take().unwrap().1;
+                    break;
+                }
+                _ = interrupt.recv() => break 'scene,
+                _ = terminate.recv() => break 'scene,
+            }
+        }
+    }
+
+    println!("exiting");
+    for screen in screens {
+        screen.output.shutdown(args.keep_on_exit)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,196 @@
+//! Monitor geometry as reported by RandR: where each monitor is in the root window, and how
+//! large it is physically. Rotated and reflected CRTCs need no special handling: monitors
+//! are given in root window coordinates, and the server turns them upright for scanout.
+
+use x11rb::connection::Connection;
+use x11rb::errors::ReplyError;
+use x11rb::protocol::randr::ConnectionExt as RandrConnectionExt;
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+
+/// A monitor in root window coordinates. Monitors may reach past the edges of the root
+/// window, and their origin may be negative.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Monitor {
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    /// physical size, 0 if unknown
+    pub width_mm: u32,
+    pub height_mm: u32,
+    /// logical to physical pixels; derived from the physical size if `None`
+    pub scale: Option<f32>,
+}
+
+impl Monitor {
+    /// The part of the monitor inside a root window of `size`, as `[x, y, width, height]`;
+    /// empty if none of it is.
+    pub fn rect(&self, size: [u16; 2]) -> [u16; 4] {
+        let clamp = |start: i16, len: u16, max: u16| {
+            let end = (start as i32 + len as i32).clamp(0, max as i32);
+            let start = (start as i32).clamp(0, end);
+            (start as u16, (end - start) as u16)
+        };
+        let (x, width) = clamp(self.x, self.width, size[0]);
+        let (y, height) = clamp(self.y, self.height, size[1]);
+        [x, y, width, height]
+    }
+}
+
+/// An upright monitor of unknown physical size covering `[x, y, width, height]`.
+impl From<[u16; 4]> for Monitor {
+    fn from([x, y, width, height]: [u16; 4]) -> Self {
+        Self { x: x as i16, y: y as i16, width, height, ..Default::default() }
+    }
+}
+
+/// Scale factors set by the user.
+#[derive(Debug, Clone, Default)]
+pub struct Scaling {
+    /// by monitor name, e.g. `DP-1`
+    pub monitors: Vec<(String, f32)>,
+    /// for the other monitors
+    pub default: Option<f32>,
+}
+
+/// The root window size and the monitors on it, as currently configured. Monitors get the
+/// scale factor `scaling` has for them; those of unknown physical size that it has none for
+/// follow `Xft.dpi`.
+pub fn query(conn: &RustConnection, root: Window, scaling: &Scaling) -> Result<([u16; 2], Vec<Monitor>), ReplyError> {
+    let geometry = conn.get_geometry(root)?.reply()?;
+    let xft_scale = xft_dpi(conn)?.map(|dpi| dpi / 96.0);
+    let mut monitors = Vec::new();
+    for m in conn.randr_get_monitors(root, false)?.reply()?.monitors {
+        let mut scale = scaling.default;
+        if !scaling.monitors.is_empty() {
+            let name = conn.get_atom_name(m.name)?.reply()?.name;
+            if let Some((_, s)) = scaling.monitors.iter().find(|(n, _)| n.as_bytes() == name) {
+                scale = Some(*s);
+            }
+        }
+        if m.width_in_millimeters == 0 || m.height_in_millimeters == 0 {
+            scale = scale.or(xft_scale);
+        }
+        monitors.push(Monitor {
+            x: m.x,
+            y: m.y,
+            width: m.width,
+            height: m.height,
+            width_mm: m.width_in_millimeters,
+            height_mm: m.height_in_millimeters,
+            scale,
+        });
+    }
+    Ok(([geometry.width, geometry.height], monitors))
+}
+
+/// `Xft.dpi` from the resources xrdb keeps on the first screen's root window.
+fn xft_dpi(conn: &RustConnection) -> Result<Option<f32>, ReplyError> {
+    let root = conn.setup().roots[0].root;
+    let resources = conn.get_property(false, root, AtomEnum::RESOURCE_MANAGER, AtomEnum::STRING, 0, u32::MAX)?.reply()?;
+    Ok(String::from_utf8_lossy(&resources.value).lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim() != "Xft.dpi" {
+            return None;
+        }
+        value.trim().parse().ok().filter(|&dpi: &f32| dpi > 0.0)
+    }))
+}
+
+/// pixel size assumed for monitors that don't report their physical size: 96 dpi
+const DEFAULT_MM_PER_PIXEL: f32 = 25.4 / 96.0;
+
+impl Monitor {
+    /// Millimeters per pixel, horizontally and vertically.
+    pub fn mm_per_pixel(&self) -> [f32; 2] {
+        if self.width_mm == 0 || self.height_mm == 0 || self.width == 0 || self.height == 0 {
+            return [DEFAULT_MM_PER_PIXEL; 2];
+        }
+        [self.width_mm as f32 / self.width as f32, self.height_mm as f32 / self.height as f32]
+    }
+
+    /// Logical to physical pixels: as set, or from the physical pixel size in steps of a
+    /// quarter, taking 96 dpi as 1. Derived factors stay between 1 and 4, as some panels
+    /// report made up sizes.
+    pub fn scale(&self) -> f32 {
+        if let Some(scale) = self.scale {
+            return scale;
+        }
+        let dpi = 25.4 / self.mm_per_pixel()[0];
+        ((dpi / 96.0 * 4.0).round() / 4.0).clamp(1.0, 4.0)
+    }
+
+    fn physical_size(&self) -> [f32; 2] {
+        let [mx, my] = self.mm_per_pixel();
+        [self.width as f32 * mx, self.height as f32 * my]
+    }
+}
+
+/// Where each monitor is in one image spread over all of them, as `[x, y, width, height]`
+/// in texture coordinates. Monitors are arranged as they stand physically: each covers as
+/// much of the image as its physical size, and neighbours are `bezel` millimeters apart
+/// horizontally and vertically, so that the parts of the image behind the bezels are not
+/// shown.
+pub fn span(monitors: &[Monitor], bezel: [f32; 2]) -> Vec<[f32; 4]> {
+    let Some(first) = (0..monitors.len()).min_by_key(|&i| (monitors[i].x, monitors[i].y)) else {
+        return Vec::new();
+    };
+
+    // physical rectangles in mm, found by walking from one monitor to its neighbours
+    let mut placed: Vec<Option<[f32; 4]>> = vec![None; monitors.len()];
+    let [w, h] = monitors[first].physical_size();
+    placed[first] = Some([0.0, 0.0, w, h]);
+    while placed.iter().any(Option::is_none) {
+        // monitors touching a placed one first, so that bezels are accounted for
+        let mut next: Option<(usize, [f32; 4], bool)> = None;
+        for n in (0..monitors.len()).filter(|&n| placed[n].is_none()) {
+            for (m, at) in placed.iter().enumerate().filter_map(|(m, p)| p.map(|p| (m, p))) {
+                let (rect, touching) = place_next_to(&monitors[m], at, &monitors[n], bezel);
+                if next.is_none_or(|(_, _, t)| touching && !t) {
+                    next = Some((n, rect, touching));
+                }
+            }
+        }
+        let (n, rect, _) = next.unwrap();
+        placed[n] = Some(rect);
+    }
+
+    let placed = placed.into_iter().map(Option::unwrap).collect::<Vec<_>>();
+    let left = placed.iter().map(|p| p[0]).fold(f32::INFINITY, f32::min);
+    let top = placed.iter().map(|p| p[1]).fold(f32::INFINITY, f32::min);
+    let right = placed.iter().map(|p| p[0] + p[2]).fold(f32::NEG_INFINITY, f32::max);
+    let bottom = placed.iter().map(|p| p[1] + p[3]).fold(f32::NEG_INFINITY, f32::max);
+    let (width, height) = (right - left, bottom - top);
+    placed.iter().map(|p| [(p[0] - left) / width, (p[1] - top) / height, p[2] / width, p[3] / height]).collect()
+}
+
+/// Physical rectangle of `n` given that of its neighbour `m`, and whether the two touch.
+/// Monitors that don't touch keep their pixel offset, measured in `m`'s pixels.
+fn place_next_to(m: &Monitor, at: [f32; 4], n: &Monitor, bezel: [f32; 2]) -> ([f32; 4], bool) {
+    let [mx, my] = m.mm_per_pixel();
+    let [w, h] = n.physical_size();
+    // edges in pixels: left, top, right, bottom
+    let edges = |m: &Monitor| {
+        let (x, y) = (i32::from(m.x), i32::from(m.y));
+        [x, y, x + i32::from(m.width), y + i32::from(m.height)]
+    };
+    let ([ml, mt, mr, mb], [nl, nt, nr, nb]) = (edges(m), edges(n));
+    let overlap_x = nl < mr && ml < nr;
+    let overlap_y = nt < mb && mt < nb;
+
+    // offset along the shared edge
+    let x = at[0] + (nl - ml) as f32 * mx;
+    let y = at[1] + (nt - mt) as f32 * my;
+    if overlap_y && nl == mr {
+        ([at[0] + at[2] + bezel[0], y, w, h], true)
+    } else if overlap_y && nr == ml {
+        ([at[0] - bezel[0] - w, y, w, h], true)
+    } else if overlap_x && nt == mb {
+        ([x, at[1] + at[3] + bezel[1], w, h], true)
+    } else if overlap_x && nb == mt {
+        ([x, at[1] - bezel[1] - h, w, h], true)
+    } else {
+        ([x, y, w, h], false)
+    }
+}
@@ -0,0 +1,104 @@
+//! Destinations for rendered frames.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::format::PixelFormat;
+use crate::render::{OutputFormat, RowLayout};
+
+/// A frame as read back from the gpu.
+pub struct Frame<'a> {
+    /// rows laid out as described by `rows`
+    pub data: &'a [u8],
+    pub rows: RowLayout,
+    pub format: OutputFormat,
+    /// rectangles `[x, y, width, height]` that changed since the previous frame
+    pub damage: &'a [[u16; 4]],
+}
+
+/// Something that shows or stores rendered frames.
+pub trait OutputBackend {
+    /// Follows a change of the frame size or monitor layout. Frames presented afterwards have
+    /// the new size, and the first of them is damaged as a whole.
+    fn configure(&mut self, size: [u16; 2], monitors: &[[u16; 4]]) -> Result<()>;
+
+    fn present(&mut self, frame: &Frame) -> Result<()>;
+}
+
+/// The parts of a frame to update: the whole frame if every monitor is `active`, otherwise
+/// the active monitors that show up in the frame.
+pub fn damage(size: [u16; 2], monitors: &[[u16; 4]], active: &[bool]) -> Vec<[u16; 4]> {
+    if active.iter().all(|&a| a) {
+        vec![[0, 0, size[0], size[1]]]
+    } else {
+        monitors.iter().zip(active).filter(|&(m, &a)| a && m[2] > 0 && m[3] > 0).map(|(m, _)| *m).collect()
+    }
+}
+
+/// Writes every frame to an image file, replacing the previous one.
+pub struct ImageFile {
+    path: PathBuf,
+}
+
+impl ImageFile {
+    /// The format follows the extension of `path` (`.png`, `.ppm`, ...).
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl OutputBackend for ImageFile {
+    fn configure(&mut self, _size: [u16; 2], _monitors: &[[u16; 4]]) -> Result<()> {
+        Ok(())
+    }
+
+    fn present(&mut self, frame: &Frame) -> Result<()> {
+        let img = crate::snapshot::to_rgba(frame.format, frame.rows, frame.data);
+        crate::snapshot::save(&img, &self.path)
+    }
+}
+
+/// Writes frames back to back as raw 32 bit BGRX pixels (ffmpeg's `bgr0`), e.g. to a pipe
+/// or FIFO. The frame size is not part of the stream.
+pub struct RawStream<W: Write> {
+    writer: W,
+    /// where `writer` leads, for errors
+    name: PathBuf,
+    buf: Vec<u8>,
+}
+
+impl RawStream<std::io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout(), "-")
+    }
+}
+
+impl RawStream<std::fs::File> {
+    /// Opens `path` for writing; a FIFO blocks until someone reads from it.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).open(path)
+            .map_err(|source| Error::Io { path: path.to_path_buf(), source })?;
+        Ok(Self::new(file, path))
+    }
+}
+
+impl<W: Write> RawStream<W> {
+    pub fn new(writer: W, name: impl Into<PathBuf>) -> Self {
+        Self { writer, name: name.into(), buf: Vec::new() }
+    }
+}
+
+impl<W: Write> OutputBackend for RawStream<W> {
+    fn configure(&mut self, _size: [u16; 2], _monitors: &[[u16; 4]]) -> Result<()> {
+        Ok(())
+    }
+
+    fn present(&mut self, frame: &Frame) -> Result<()> {
+        self.buf.resize(frame.rows.bytes_per_row * frame.rows.rows, 0);
+        PixelFormat::BGRX.write_frame(frame.format, frame.rows, frame.data, &mut self.buf);
+        self.writer.write_all(&self.buf)
+            .and_then(|()| self.writer.flush())
+            .map_err(|source| Error::Io { path: self.name.clone(), source })
+    }
+}
@@ -0,0 +1,212 @@
+//! Server-side pixmaps that frames are uploaded into, with or without MIT-SHM.
+
+use std::borrow::Cow;
+
+use x11rb::connection::Connection;
+use x11rb::errors::ConnectionError;
+use x11rb::image::{BitsPerPixel, Image, ImageOrder, ScanlinePad};
+use x11rb::protocol::shm::ConnectionExt as ShmConnectionExt;
+use x11rb::protocol::xproto::{ConnectionExt, Drawable, Gcontext, ImageFormat, Pixmap};
+use x11rb::rust_connection::RustConnection;
+
+use crate::error::{Error, Result};
+use crate::format::PixelFormat;
+use crate::shm::ShmPixmap;
+
+/// A plain pixmap filled with core `PutImage` requests, for servers without usable shared
+/// memory such as `ssh -X` or nested servers.
+pub struct CorePixmap {
+    pub pixmap: Pixmap,
+    pub width: u16,
+    pub height: u16,
+    pub format: PixelFormat,
+    pub drawable: Drawable,
+
+    data: Vec<u8>,
+}
+
+impl CorePixmap {
+    pub fn new(
+        connection: &RustConnection,
+        drawable: Drawable,
+        width: u16,
+        height: u16,
+        format: PixelFormat,
+    ) -> Result<Self> {
+        let pixmap = connection.generate_id()?;
+        connection.create_pixmap(format.depth, pixmap, drawable, width, height)?;
+
+        Ok(Self {
+            pixmap,
+            width,
+            height,
+            format,
+            drawable,
+            data: vec![0; format.stride(width) * height as usize],
+        })
+    }
+
+    pub fn as_slice(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Uploads the rectangle `[x, y, width, height]` of the local buffer to the pixmap.
+    /// Requests are split up to stay within the server's maximum request length.
+    pub fn upload(&self, connection: &RustConnection, gc: Gcontext, rect: [u16; 4]) -> Result<()> {
+        let [x, y, w, h] = rect;
+        let stride = self.format.stride(self.width);
+        let bpp = self.format.bytes_per_pixel();
+
+        // whole rows can be sent as they are; anything narrower is cut out first
+        let data = if x == 0 && w == self.width {
+            Cow::Borrowed(&self.data[y as usize * stride..(y + h) as usize * stride])
+        } else {
+            let sub_stride = self.format.stride(w);
+            let mut sub = vec![0; sub_stride * h as usize];
+            for (dst, src) in sub.chunks_exact_mut(sub_stride).zip(self.data.chunks_exact(stride).skip(y as usize)) {
+                let start = x as usize * bpp;
+                dst[..w as usize * bpp].copy_from_slice(&src[start..start + w as usize * bpp]);
+            }
+            Cow::Owned(sub)
+        };
+
+        let scanline_pad = ScanlinePad::try_from(self.format.scanline_pad)
+            .map_err(|_| Error::UnsupportedFormat(format!("scanline pad of {} bits", self.format.scanline_pad)))?;
+        let bits_per_pixel = BitsPerPixel::try_from(self.format.bits_per_pixel)
+            .map_err(|_| Error::UnsupportedFormat(format!("{} bits per pixel", self.format.bits_per_pixel)))?;
+        let image = Image::new(
+            w,
+            h,
+            scanline_pad,
+            self.format.depth,
+            bits_per_pixel,
+            if self.format.big_endian { ImageOrder::MsbFirst } else { ImageOrder::LsbFirst },
+            data,
+        ).map_err(|e| Error::UnsupportedFormat(format!("image of {}x{}: {:?}", w, h, e)))?;
+        image.put(connection, self.pixmap, gc, x as i16, y as i16)?;
+        Ok(())
+    }
+
+    pub fn free(self, connection: &RustConnection) -> Result<(), ConnectionError> {
+        connection.free_pixmap(self.pixmap)?;
+        Ok(())
+    }
+}
+
+/// The pixmap frames are written to, with whichever upload path the server supports.
+pub enum FramePixmap {
+    Shm(ShmPixmap),
+    Core(CorePixmap),
+}
+
+impl FramePixmap {
+    /// Uses shared memory if the server can attach it, and core requests otherwise.
+    pub fn new(
+        connection: &RustConnection,
+        drawable: Drawable,
+        width: u16,
+        height: u16,
+        format: PixelFormat,
+    ) -> Result<Self> {
+        match ShmPixmap::new(connection, drawable, width, height, format) {
+            Ok(pm) => Ok(FramePixmap::Shm(pm)),
+            Err(_) => Ok(FramePixmap::Core(CorePixmap::new(connection, drawable, width, height, format)?)),
+        }
+    }
+
+    pub fn pixmap(&self) -> Pixmap {
+        match self {
+            FramePixmap::Shm(pm) => pm.pixmap,
+            FramePixmap::Core(pm) => pm.pixmap,
+        }
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        match self {
+            FramePixmap::Shm(pm) => pm.format,
+            FramePixmap::Core(pm) => pm.format,
+        }
+    }
+
+    /// True if frames travel through shared memory, i.e. the server is local.
+    pub fn is_shm(&self) -> bool {
+        matches!(self, FramePixmap::Shm(_))
+    }
+
+    /// The client-side image to write frames into.
+    pub fn as_slice(&mut self) -> &mut [u8] {
+        match self {
+            FramePixmap::Shm(pm) => pm.shmseg.as_slice(),
+            FramePixmap::Core(pm) => pm.as_slice(),
+        }
+    }
+
+    /// Shows the given rectangles of the current frame on `window`.
+    pub fn draw(
+        &self,
+        connection: &RustConnection,
+        gc: Gcontext,
+        window: Drawable,
+        rects: &[[u16; 4]],
+    ) -> Result<()> {
+        for &r in rects {
+            match self {
+                FramePixmap::Shm(pm) => {
+                    connection.shm_put_image(
+                        window,
+                        gc,
+                        pm.width,
+                        pm.height,
+                        r[0],
+                        r[1],
+                        r[2],
+                        r[3],
+                        r[0] as i16,
+                        r[1] as i16,
+                        pm.format.depth,
+                        ImageFormat::Z_PIXMAP.into(),
+                        true,
+                        pm.shmseg.seg,
+                        0
+                    )?;
+                }
+                FramePixmap::Core(pm) => {
+                    // upload once into the pixmap, then copy server-side
+                    pm.upload(connection, gc, r)?;
+                    connection.copy_area(pm.pixmap, window, gc, r[0] as i16, r[1] as i16, r[0] as i16, r[1] as i16, r[2], r[3])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Brings the given rectangles of the pixmap up to date with the current frame without
+    /// drawing them anywhere. Shared memory pixmaps are always up to date.
+    pub fn upload(&self, connection: &RustConnection, gc: Gcontext, rects: &[[u16; 4]]) -> Result<()> {
+        if let FramePixmap::Core(pm) = self {
+            for &r in rects {
+                pm.upload(connection, gc, r)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the pixmap with a new one of the given size, using the same upload path.
+    /// The previous pixmap is returned and stays valid until it is `free`d.
+    pub fn resize(&mut self, connection: &RustConnection, width: u16, height: u16) -> Result<FramePixmap> {
+        Ok(match self {
+            FramePixmap::Shm(pm) => FramePixmap::Shm(pm.resize(connection, width, height)?),
+            FramePixmap::Core(pm) => {
+                let new = CorePixmap::new(connection, pm.drawable, width, height, pm.format)?;
+                FramePixmap::Core(std::mem::replace(pm, new))
+            }
+        })
+    }
+
+    pub fn free(self, connection: &RustConnection) -> Result<(), ConnectionError> {
+        match self {
+            FramePixmap::Shm(pm) => pm.free(connection),
+            FramePixmap::Core(pm) => pm.free(connection),
+        }
+    }
+}
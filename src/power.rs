@@ -0,0 +1,123 @@
+//! Frame-rate policy driven by battery and thermal state read from sysfs.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy)]
+pub struct PowerConfig {
+    /// frame rate on AC power
+    pub fps: f32,
+    /// frame rate while discharging
+    pub battery_fps: f32,
+    /// frame rate while discharging below `low_battery_percent`
+    pub low_battery_fps: f32,
+    pub low_battery_percent: u8,
+    /// frame rate while any thermal zone is at or above `hot_celsius`
+    pub hot_fps: f32,
+    pub hot_celsius: f32,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            fps: 60.0,
+            battery_fps: 30.0,
+            low_battery_fps: 0.0,
+            low_battery_percent: 20,
+            hot_fps: 10.0,
+            hot_celsius: 85.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PowerState {
+    pub on_battery: bool,
+    /// lowest charge of all batteries, in percent
+    pub capacity: Option<u8>,
+    /// hottest thermal zone, in degrees celsius
+    pub temperature: Option<f32>,
+}
+
+pub struct PowerPolicy {
+    sysfs: PathBuf,
+    config: PowerConfig,
+}
+
+impl PowerPolicy {
+    /// `sysfs` is normally `/sys`; tests can point it at a directory of fake files
+    /// laid out like `class/power_supply/*` and `class/thermal/thermal_zone*`.
+    pub fn new(sysfs: impl Into<PathBuf>, config: PowerConfig) -> Self {
+        Self { sysfs: sysfs.into(), config }
+    }
+
+    pub fn read_state(&self) -> PowerState {
+        let mut state = PowerState::default();
+        let mut mains_online = false;
+        let mut discharging = false;
+
+        for dir in list_dir(&self.sysfs.join("class/power_supply")) {
+            match read_trimmed(&dir.join("type")).as_deref() {
+                Some("Mains") | Some("USB") => {
+                    mains_online |= read_trimmed(&dir.join("online")).as_deref() == Some("1");
+                }
+                Some("Battery") => {
+                    // peripherals (mice, headsets) report their batteries here too
+                    if read_trimmed(&dir.join("scope")).as_deref() == Some("Device") {
+                        continue;
+                    }
+                    discharging |= read_trimmed(&dir.join("status")).as_deref() == Some("Discharging");
+                    if let Some(c) = read_trimmed(&dir.join("capacity")).and_then(|s| s.parse::<u8>().ok()) {
+                        state.capacity = Some(state.capacity.map_or(c, |prev| prev.min(c)));
+                    }
+                }
+                _ => {}
+            }
+        }
+        state.on_battery = discharging || (state.capacity.is_some() && !mains_online);
+
+        for dir in list_dir(&self.sysfs.join("class/thermal")) {
+            if !dir.file_name().is_some_and(|n| n.to_string_lossy().starts_with("thermal_zone")) {
+                continue;
+            }
+            // millidegrees celsius
+            if let Some(t) = read_trimmed(&dir.join("temp")).and_then(|s| s.parse::<i64>().ok()) {
+                let t = t as f32 / 1000.0;
+                state.temperature = Some(state.temperature.map_or(t, |prev| prev.max(t)));
+            }
+        }
+
+        state
+    }
+
+    /// Frame rate for `state`; 0 means animation should pause.
+    pub fn fps_for(&self, state: &PowerState) -> f32 {
+        let c = &self.config;
+        let mut fps = c.fps;
+        if state.on_battery {
+            fps = fps.min(c.battery_fps);
+            if state.capacity.is_some_and(|cap| cap < c.low_battery_percent) {
+                fps = fps.min(c.low_battery_fps);
+            }
+        }
+        if state.temperature.is_some_and(|t| t >= c.hot_celsius) {
+            fps = fps.min(c.hot_fps);
+        }
+        fps.max(0.0)
+    }
+
+    /// Reads the current state and returns the frame rate to use.
+    pub fn fps(&self) -> f32 {
+        self.fps_for(&self.read_state())
+    }
+}
+
+fn list_dir(path: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(path) else { return Vec::new() };
+    let mut dirs = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<_>>();
+    dirs.sort();
+    dirs
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
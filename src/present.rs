@@ -0,0 +1,117 @@
+//! Frame timing from the display refresh, via the Present extension.
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::errors::ReplyError;
+use x11rb::protocol::Event;
+use x11rb::protocol::present::{self, CompleteKind, ConnectionExt as PresentConnectionExt};
+use x11rb::protocol::randr::{self, ConnectionExt as RandrConnectionExt};
+use x11rb::protocol::xproto::Window;
+use x11rb::rust_connection::RustConnection;
+
+/// Asks the server to notify us every `divisor` vblanks of the monitor showing `window`.
+pub struct FrameClock {
+    window: Window,
+    eid: u32,
+    divisor: u64,
+
+    serial: u32,
+    last_msc: u64,
+    pending: bool,
+}
+
+impl FrameClock {
+    /// Returns `None` if the server has no Present extension.
+    pub fn new(conn: &RustConnection, window: Window, divisor: u64) -> crate::Result<Option<Self>> {
+        if conn.extension_information(present::X11_EXTENSION_NAME)?.is_none() {
+            return Ok(None);
+        }
+        conn.present_query_version(1, 0)?.reply()?;
+
+        let eid = conn.generate_id()?;
+        conn.present_select_input(eid, window, present::EventMask::COMPLETE_NOTIFY)?;
+
+        Ok(Some(Self {
+            window,
+            eid,
+            divisor: divisor.max(1),
+            serial: 0,
+            last_msc: 0,
+            pending: false,
+        }))
+    }
+
+    pub fn set_divisor(&mut self, divisor: u64) {
+        self.divisor = divisor.max(1);
+    }
+
+    /// Requests a notification for the next frame, unless one is already outstanding.
+    pub fn request(&mut self, conn: &RustConnection) -> Result<(), ReplyError> {
+        if self.pending {
+            return Ok(());
+        }
+        self.serial = self.serial.wrapping_add(1);
+        // before the first notification last_msc is 0, which completes immediately and tells
+        // us the current msc
+        let target = if self.last_msc == 0 { 0 } else { self.last_msc + self.divisor };
+        conn.present_notify_msc(self.window, self.serial, target, 0, 0)?;
+        self.pending = true;
+        Ok(())
+    }
+
+    /// Forgets the outstanding request, e.g. when the display stopped counting vblanks
+    /// while powered down.
+    pub fn reset(&mut self) {
+        self.pending = false;
+        self.last_msc = 0;
+    }
+
+    /// Returns true if `event` completes our outstanding request, i.e. a frame is due.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        match event {
+            Event::PresentCompleteNotify(e)
+                if e.event == self.eid && e.kind == CompleteKind::NOTIFY_MSC && e.serial == self.serial => {
+                self.last_msc = e.msc;
+                self.pending = false;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Highest refresh rate in Hz of all active CRTCs, from their RandR modes.
+pub fn refresh_rate(conn: &RustConnection, root: Window) -> Result<Option<f32>, ReplyError> {
+    let resources = conn.randr_get_screen_resources_current(root)?.reply()?;
+
+    let mut best: Option<f32> = None;
+    for &crtc in &resources.crtcs {
+        let info = conn.randr_get_crtc_info(crtc, resources.config_timestamp)?.reply()?;
+        if info.mode == 0 {
+            continue;
+        }
+        let Some(mode) = resources.modes.iter().find(|m| m.id == info.mode) else { continue };
+        if mode.htotal == 0 || mode.vtotal == 0 {
+            continue;
+        }
+
+        let flags = u32::from(mode.mode_flags);
+        let mut vtotal = mode.vtotal as f32;
+        if flags & u32::from(randr::ModeFlag::DOUBLE_SCAN) != 0 {
+            vtotal *= 2.0;
+        }
+        if flags & u32::from(randr::ModeFlag::INTERLACE) != 0 {
+            vtotal /= 2.0;
+        }
+
+        let rate = mode.dot_clock as f32 / (mode.htotal as f32 * vtotal);
+        best = Some(best.map_or(rate, |b| b.max(rate)));
+    }
+    Ok(best)
+}
+
+/// Smallest vblank divisor that keeps the frame rate at or below `fps`, but at least `divisor`.
+pub fn divisor_for(refresh: f32, fps: f32, divisor: u64) -> u64 {
+    // mode timings rarely give an exact rate (e.g. 60.0004Hz); don't let that halve 60fps
+    let needed = (refresh / fps - 0.01).ceil() as u64;
+    needed.max(divisor).max(1)
+}
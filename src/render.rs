@@ -1,6 +1,14 @@
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
 use wgpu::util::DeviceExt;
 
-use crate::texture::Texture;
+use crate::error::{Error, Result};
+use crate::monitor::Monitor;
+use crate::loader::ImageLoader;
+use crate::scene::{ImageSource, Motion, Placement, Scene};
+use crate::texture::{Texture, TextureCache};
 // use image::{ImageBuffer, Rgba};
 
 #[repr(C)]
@@ -8,13 +16,15 @@ use crate::texture::Texture;
 pub struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
-    tex_id: u32,
+    /// size in logical pixels of a layer drawn at a fixed size around `position`; zero for
+    /// layers stretched over the monitor
+    extent: [f32; 2],
 }
 impl Vertex {
     const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
         0 => Float32x3,
         1 => Float32x2,
-        2 => Uint32,
+        2 => Float32x2,
     ];
 
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -26,18 +36,12 @@ impl Vertex {
     }
 }
 
+// one quad per layer
 const VERTICES: &[Vertex] = &[
-    // bg
-    Vertex { tex_id: 0, position: [1.0, 0.0, 0.0], tex_coords: [1.0, 1.0], },
-    Vertex { tex_id: 0, position: [1.0, 1.0, 0.0], tex_coords: [1.0, 0.0], },
-    Vertex { tex_id: 0, position: [0.0, 0.0, 0.0], tex_coords: [0.0, 1.0], },
-    Vertex { tex_id: 0, position: [0.0, 1.0, 0.0], tex_coords: [0.0, 0.0], },
-
-    // fg
-    Vertex { tex_id: 1, position: [1.0, 0.0, 0.0], tex_coords: [1.0, 1.0], },
-    Vertex { tex_id: 1, position: [1.0, 1.0, 0.0], tex_coords: [1.0, 0.0], },
-    Vertex { tex_id: 1, position: [0.0, 0.0, 0.0], tex_coords: [0.0, 1.0], },
-    Vertex { tex_id: 1, position: [0.0, 1.0, 0.0], tex_coords: [0.0, 0.0], },
+    Vertex { position: [1.0, 0.0, 0.0], tex_coords: [1.0, 1.0], extent: [0.0, 0.0], },
+    Vertex { position: [1.0, 1.0, 0.0], tex_coords: [1.0, 0.0], extent: [0.0, 0.0], },
+    Vertex { position: [0.0, 0.0, 0.0], tex_coords: [0.0, 1.0], extent: [0.0, 0.0], },
+    Vertex { position: [0.0, 1.0, 0.0], tex_coords: [0.0, 0.0], extent: [0.0, 0.0], },
 ];
 
 #[repr(C)]
@@ -45,12 +49,24 @@ const VERTICES: &[Vertex] = &[
 struct Instance {
     position: [f32; 2], // bottom left
     size: [f32; 2],
+    /// rows of a matrix scaling texture coordinates, centered on 0, to the part of the image
+    /// shown
+    tex_u: [f32; 2],
+    tex_v: [f32; 2],
+    /// center of the part of the image shown
+    tex_center: [f32; 2],
+    /// fraction of the quad per logical pixel, from the monitor's scale factor
+    logical_px: [f32; 2],
 }
 
 impl Instance {
-    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+    const ATTRIBS: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
         3 => Float32x2,
         4 => Float32x2,
+        5 => Float32x2,
+        6 => Float32x2,
+        7 => Float32x2,
+        8 => Float32x2,
     ];
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
@@ -65,46 +81,351 @@ impl Instance {
     }
 }
 
+const BACKGROUND: wgpu::Color = wgpu::Color { r: 0.1, g: 0.2, b: 0.3, a: 1.0 };
+// same color as BACKGROUND, encoded as srgb
+const BACKGROUND_SRGB: [u8; 4] = [89, 124, 149, 255];
 
-pub struct Renderer<'a> {
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+/// Where a layer goes and how it moves.
+#[derive(Debug, Clone, Copy, Default)]
+struct LayerLayout {
+    motion: Motion,
+    placement: Placement,
+    /// size in logical pixels, centered on the monitor; stretched over it if `None`
+    size: Option<[f32; 2]>,
+}
 
-    texture: wgpu::Texture,
-    texture_desc: wgpu::TextureDescriptor<'a>,
-    texture_view: wgpu::TextureView,
+struct LayerState {
+    // kept alive for the bind group
+    _texture: Arc<Texture>,
+    bind_group: wgpu::BindGroup,
+    layout: LayerLayout,
+}
 
-    vertex_buffer: wgpu::Buffer,
+impl LayerState {
+    fn new(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        texture: Arc<Texture>,
+        layout: LayerLayout,
+    ) -> Self {
+        let bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                layout: bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+                label: Some("diffuse_bind_group"),
+            }
+        );
+        Self { _texture: texture, bind_group, layout }
+    }
+}
 
-    // output_buffer_desc: wgpu::BufferDescriptor<'a>,
-    output_buffer: wgpu::Buffer,
+/// number of frames that can be in flight between rendering and readback
+const READBACK_BUFFERS: usize = 3;
+/// weight of the newest sample in the moving averages of `FrameStats`
+const STATS_SMOOTHING: f32 = 0.1;
 
-    render_pipeline: wgpu::RenderPipeline,
+struct OutputBuffer {
+    buffer: wgpu::Buffer,
+    submitted: Option<Instant>,
+    /// result of `map_async`, while the frame is in flight
+    mapped: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
 
-    diffuse0_bind_group: wgpu::BindGroup,
-    diffuse0_texture: Texture,
-    diffuse1_bind_group: wgpu::BindGroup,
-    diffuse1_texture: Texture,
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    /// frames read back
+    pub frames: u64,
+    /// frames rendered but superseded before anyone read them
+    pub dropped: u64,
+    /// average time from submission to readback
+    pub latency: Duration,
+    /// average read back frames per second
+    pub throughput: f32,
+}
 
-    instance_buffer: wgpu::Buffer,
-    instance_len: usize,
+/// Pixel format of rendered frames. Both are 32 bits per pixel, little endian.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// 8 bit srgb, blue in the lowest byte
+    #[default]
+    Bgra8,
+    /// 10 bit srgb-encoded color, blue in the lowest bits and 2 bits of alpha on top;
+    /// the layout of depth 30 X visuals
+    Bgr10,
 }
 
-impl<'a> Renderer<'a> {
-    pub async fn new(
-        size: [u16; 2],
-        monitors: &[[u16; 4]],
-    ) -> Renderer<'a> {
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+impl OutputFormat {
+    /// Format of the textures frames are rendered into.
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            OutputFormat::Bgra8 => wgpu::TextureFormat::Bgra8UnormSrgb,
+            // there is no bgr 10 bit format; the shader swaps red and blue instead
+            OutputFormat::Bgr10 => wgpu::TextureFormat::Rgb10a2Unorm,
+        }
+    }
 
-        let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::default(),
-            compatible_surface: None,
-            force_fallback_adapter: false,
-        }).await.unwrap();
+    fn fragment_entry_point(self) -> &'static str {
+        match self {
+            OutputFormat::Bgra8 => "fs_main",
+            OutputFormat::Bgr10 => "fs_main_bgr10",
+        }
+    }
+
+    /// `color` (linear) as it has to be written to a texture of this format.
+    fn clear_color(self, color: wgpu::Color) -> wgpu::Color {
+        fn encode(c: f64) -> f64 {
+            if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+        }
+        match self {
+            OutputFormat::Bgra8 => color,
+            OutputFormat::Bgr10 => wgpu::Color {
+                r: encode(color.b),
+                g: encode(color.g),
+                b: encode(color.r),
+                a: color.a,
+            },
+        }
+    }
+}
+
+/// Rows of a frame as read back from the gpu.
+/// wgpu requires each row to start at a multiple of `COPY_BYTES_PER_ROW_ALIGNMENT`,
+/// so rows carry padding unless the width is a multiple of 64 pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RowLayout {
+    /// bytes of pixel data in a row
+    pub bytes_per_row: usize,
+    /// distance between the starts of two rows
+    pub padded_bytes_per_row: usize,
+    pub rows: usize,
+}
+
+impl RowLayout {
+    pub fn new(width: u32, height: u32) -> Self {
+        let bytes_per_row = std::mem::size_of::<u32>() * width as usize;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize;
+        Self {
+            bytes_per_row,
+            padded_bytes_per_row: bytes_per_row.div_ceil(align) * align,
+            rows: height as usize,
+        }
+    }
+
+    /// Copies a padded frame into `dst` with rows packed back to back.
+    pub fn copy_to(&self, src: &[u8], dst: &mut [u8]) {
+        if self.bytes_per_row == self.padded_bytes_per_row {
+            dst.copy_from_slice(src);
+            return;
+        }
+        for (dst, src) in dst
+            .chunks_exact_mut(self.bytes_per_row)
+            .zip(src.chunks(self.padded_bytes_per_row))
+        {
+            dst.copy_from_slice(&src[..self.bytes_per_row]);
+        }
+    }
+}
+
+/// A layer handed to `RendererBuilder`, not yet on the gpu.
+enum LayerSource {
+    Image(image::DynamicImage, String),
+    Texture(Arc<Texture>),
+}
+
+/// Opens a device for offscreen rendering, to be shared by several renderers through
+/// `RendererBuilder::device`. `fallback_adapter` picks wgpu's software adapter.
+pub async fn request_device(fallback_adapter: bool) -> Result<(Arc<wgpu::Device>, Arc<wgpu::Queue>)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+
+    let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: None,
+        force_fallback_adapter: fallback_adapter,
+    }).await.ok_or(Error::NoAdapter)?;
+
+    let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor::default(), None).await?;
+    Ok((Arc::new(device), Arc::new(queue)))
+}
+
+/// Sets up a `Renderer` for programs that bring their own layers or gpu device.
+///
+/// ```no_run
+/// # async fn f(img: image::DynamicImage) -> xbg::Result<()> {
+/// use xbg::render::RendererBuilder;
+/// use xbg::scene::Motion;
+///
+/// let mut renderer = RendererBuilder::new([1920, 1080])
+///     .layer(img, "wallpaper", Motion::None)
+///     .build()
+///     .await?;
+/// renderer.render(std::time::Duration::ZERO, |frame| {
+///     // BGRA rows, see `Renderer::row_layout`
+/// }).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RendererBuilder {
+    size: [u16; 2],
+    monitors: Option<Vec<Monitor>>,
+    format: OutputFormat,
+    layers: Vec<(LayerSource, LayerLayout)>,
+    bezel: [f32; 2],
+    device: Option<(Arc<wgpu::Device>, Arc<wgpu::Queue>)>,
+    fallback_adapter: bool,
+    readback: bool,
+}
 
+impl RendererBuilder {
+    /// A renderer for frames of `size` pixels, with no layers and a single monitor covering
+    /// the whole frame.
+    pub fn new(size: [u16; 2]) -> Self {
+        Self {
+            size,
+            monitors: None,
+            format: OutputFormat::default(),
+            layers: Vec::new(),
+            bezel: [0.0; 2],
+            device: None,
+            fallback_adapter: false,
+            readback: true,
+        }
+    }
 
-        let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor::default(), None).await.unwrap();
+    /// Monitors within the frame, which each get a copy of the layers.
+    pub fn monitors(mut self, monitors: &[Monitor]) -> Self {
+        self.monitors = Some(monitors.to_vec());
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Adds a layer on top of the previous ones. `label` shows up in gpu debugging tools.
+    pub fn layer(mut self, image: image::DynamicImage, label: &str, motion: Motion) -> Self {
+        self.layers.push((LayerSource::Image(image, label.to_string()), LayerLayout { motion, ..Default::default() }));
+        self
+    }
+
+    /// Adds a layer from a texture that is already on the gpu.
+    /// The texture must come from the device passed to `device`, and may be shared with
+    /// other renderers on it.
+    pub fn texture_layer(mut self, texture: impl Into<Arc<Texture>>, motion: Motion) -> Self {
+        self.layers.push((LayerSource::Texture(texture.into()), LayerLayout { motion, ..Default::default() }));
+        self
+    }
+
+    /// Lays out the layer added last as given; layers go on every monitor by default.
+    pub fn placement(mut self, placement: Placement) -> Self {
+        if let Some((_, layout)) = self.layers.last_mut() {
+            layout.placement = placement;
+        }
+        self
+    }
+
+    /// Draws the layer added last at `size` logical pixels, centered on each monitor and
+    /// scaled by the monitor's scale factor, instead of stretching it over the monitor.
+    pub fn size(mut self, size: Option<[f32; 2]>) -> Self {
+        if let Some((_, layout)) = self.layers.last_mut() {
+            layout.size = size;
+        }
+        self
+    }
+
+    /// Gap between neighbouring panels in mm, horizontally and vertically, that spanned
+    /// layers leave out.
+    pub fn bezel(mut self, bezel: [f32; 2]) -> Self {
+        self.bezel = bezel;
+        self
+    }
+
+    /// Adds the layers of `scene`, loading their images in parallel and scaled down to the
+    /// size they are shown at on `monitors`, which must be set before.
+    pub fn scene(mut self, scene: &Scene) -> Result<Self> {
+        let requests = self.image_requests(scene);
+        let loaded = ImageLoader::new(0).load_all(&requests);
+        for (layer, image) in scene.layers.iter().zip(loaded) {
+            let image = image::DynamicImage::ImageRgba8(Arc::unwrap_or_clone(image?));
+            self = self.layer(image, &layer.image.label(), layer.motion)
+                .placement(layer.placement)
+                .size(layer.size);
+        }
+        Ok(self.bezel(scene.bezel))
+    }
+
+    /// Adds the layers of `scene` with textures from `cache`, and renders on its device.
+    /// Images already on the gpu for another renderer are not loaded again. Like `scene`,
+    /// this needs `monitors` set before.
+    pub fn cached_scene(mut self, scene: &Scene, cache: &mut TextureCache) -> Result<Self> {
+        let (device, queue) = cache.device();
+        self = self.device(device.clone(), queue.clone());
+        let textures = cache.get_all(&self.image_requests(scene))?;
+        for (layer, texture) in scene.layers.iter().zip(textures) {
+            self = self.texture_layer(texture, layer.motion)
+                .placement(layer.placement)
+                .size(layer.size);
+        }
+        Ok(self.bezel(scene.bezel))
+    }
+
+    /// The images of `scene` and the largest size each is shown at on `monitors`.
+    pub fn image_requests<'s>(&self, scene: &'s Scene) -> Vec<(&'s ImageSource, [u32; 2])> {
+        let full = [Monitor::from([0, 0, self.size[0], self.size[1]])];
+        let monitors = self.monitors.as_deref().unwrap_or(&full);
+        let span = crate::monitor::span(monitors, scene.bezel);
+        scene.layers.iter().map(|layer| {
+            let mut shown = [0.0f32; 2];
+            for (m, &[_, _, w, h]) in monitors.iter().zip(&span) {
+                let [width, height] = [m.width as f32, m.height as f32];
+                let on_monitor = match (layer.placement, layer.size) {
+                    (Placement::Monitor, Some([w, h])) => [w * m.scale(), h * m.scale()],
+                    (Placement::Monitor, None) => [width, height],
+                    (Placement::Span, _) => [width / w, height / h],
+                };
+                shown = [shown[0].max(on_monitor[0]), shown[1].max(on_monitor[1])];
+            }
+            (&layer.image, shown.map(|s| s.ceil() as u32))
+        }).collect()
+    }
+
+    /// Renders with an existing device instead of opening a new one.
+    pub fn device(mut self, device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
+        self.device = Some((device, queue));
+        self
+    }
+
+    /// Renders on wgpu's software adapter, so that output doesn't depend on the gpu.
+    /// Ignored if `device` is given.
+    pub fn fallback_adapter(mut self, fallback: bool) -> Self {
+        self.fallback_adapter = fallback;
+        self
+    }
+
+    /// Without readback no buffers are allocated to read frames into, and only `render_to`
+    /// can be used; for renderers that draw straight onto a window surface.
+    pub fn readback(mut self, readback: bool) -> Self {
+        self.readback = readback;
+        self
+    }
+
+    pub async fn build<'a>(self) -> Result<Renderer<'a>> {
+        let RendererBuilder { size, monitors, format, layers: sources, bezel, device, fallback_adapter, readback } = self;
+        let monitors = monitors.unwrap_or_else(|| vec![Monitor::from([0, 0, size[0], size[1]])]);
+
+        let (device, queue) = match device {
+            Some(dq) => dq,
+            None => request_device(fallback_adapter).await?,
+        };
 
         let texture_desc = wgpu::TextureDescriptor {
             size: wgpu::Extent3d {
@@ -115,7 +436,7 @@ impl<'a> Renderer<'a> {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            format: format.texture_format(),
             usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
             label: Some("texture"),
             view_formats: &[],
@@ -124,23 +445,15 @@ impl<'a> Renderer<'a> {
         let texture = device.create_texture(&texture_desc);
         let texture_view = texture.create_view(&Default::default());
 
-        // we need to store this for later
-        let u32_size = std::mem::size_of::<u32>() as u32;
-
-        let output_buffer_size = (u32_size * size[0] as u32 * size[1] as u32) as wgpu::BufferAddress;
-        let output_buffer_desc = wgpu::BufferDescriptor {
-            size: output_buffer_size,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-            label: Some("output_buffer"),
-            mapped_at_creation: false,
-        };
-        let output_buffer = device.create_buffer(&output_buffer_desc);
+        let output_buffers = create_output_buffers(&device, size, if readback { READBACK_BUFFERS } else { 0 });
 
+        // report shader and pipeline validation errors instead of panicking in the default handler
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
         let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
 
         let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Vertex Buffer"),
-            size: std::mem::size_of::<Vertex>() as wgpu::BufferAddress * 4 * 2,
+            size: std::mem::size_of::<Vertex>() as wgpu::BufferAddress * 4 * (sources.len() + 1) as wgpu::BufferAddress,
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
@@ -170,73 +483,27 @@ impl<'a> Renderer<'a> {
                 label: Some("texture_bind_group_layout"),
             });
 
-        let diffuse0_bytes = include_bytes!("happy-tree.png");
-        let diffuse0_texture = Texture::from_bytes(&device, &queue, diffuse0_bytes, "happy-tree.png").unwrap();
-
-        let diffuse0_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse0_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse0_texture.sampler),
-                    },
-                ],
-                label: Some("diffuse_bind_group"),
-            }
-        );
-        let diffuse1_bytes = include_bytes!("favicon.png");
-        let diffuse1_texture = Texture::from_bytes(&device, &queue, diffuse1_bytes, "favicon.png").unwrap();
-
-        let diffuse1_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse1_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse1_texture.sampler),
-                    },
-                ],
-                label: Some("diffuse_bind_group"),
-            }
-        );
-
-        let instances = monitors.iter().map(|m| {
-            Instance {
-                position: [
-                    m[0] as f32 / size[0] as f32 * 2.0 - 1.0,
-                    1.0 - (m[1] + m[3]) as f32 / size[1] as f32 * 2.0,
-                ],
-                size: [
-                    m[2] as f32 / size[0] as f32 * 2.0,
-                    m[3] as f32 / size[1] as f32 * 2.0,
-                ],
-            }
-        }).collect::<Vec<_>>();
-        println!("{:?}", instances);
-
-        let instance_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instances),
-                usage: wgpu::BufferUsages::VERTEX,
-            }
-        );
+        // the background is drawn as a layer too, so that a single monitor can be cleared
+        // without touching the others
+        let background = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(BACKGROUND_SRGB)));
+        let background = Arc::new(Texture::from_image(&device, &queue, &background, Some("background"))?);
+        let mut layers = vec![
+            LayerState::new(&device, &texture_bind_group_layout, background, LayerLayout::default()),
+        ];
+        for (source, layout) in sources {
+            let texture = match source {
+                LayerSource::Image(img, label) => Arc::new(Texture::from_image(&device, &queue, &img, Some(&label))?),
+                LayerSource::Texture(texture) => texture,
+            };
+            layers.push(LayerState::new(&device, &texture_bind_group_layout, texture, layout));
+        }
 
+        let instance_buffer = create_instance_buffer(&device, size, &monitors, bezel);
 
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
             bind_group_layouts: &[
                 &texture_bind_group_layout,
-                &texture_bind_group_layout,
             ],
             push_constant_ranges: &[],
         });
@@ -251,7 +518,7 @@ impl<'a> Renderer<'a> {
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: "fs_main",
+                entry_point: format.fragment_entry_point(),
                 targets: &[Some(wgpu::ColorTargetState {
                     format: texture_desc.format,
                     blend: Some(wgpu::BlendState::ALPHA_BLENDING),
@@ -276,9 +543,11 @@ impl<'a> Renderer<'a> {
             },
             multiview: None,
         });
-        println!("render pipeline created");
+        if let Some(e) = device.pop_error_scope().await {
+            return Err(Error::Shader(e.to_string()));
+        }
 
-        Self {
+        Ok(Renderer {
             device,
             queue,
             texture,
@@ -286,29 +555,162 @@ impl<'a> Renderer<'a> {
             texture_view,
             vertex_buffer,
             // output_buffer_desc,
-            output_buffer,
+            output_buffers,
+            in_flight: VecDeque::new(),
+            stats: FrameStats::default(),
+            last_completed: None,
 
             render_pipeline,
 
-            diffuse0_bind_group,
-            diffuse0_texture,
-            diffuse1_bind_group,
-            diffuse1_texture,
+            layers,
+            format,
 
             instance_buffer,
-            instance_len: instances.len(),
+            active: vec![true; monitors.len()],
+            monitors,
+            bezel,
+        })
+    }
+}
+
+pub struct Renderer<'a> {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+
+    texture: wgpu::Texture,
+    texture_desc: wgpu::TextureDescriptor<'a>,
+    texture_view: wgpu::TextureView,
+
+    vertex_buffer: wgpu::Buffer,
+
+    // output_buffer_desc: wgpu::BufferDescriptor<'a>,
+    output_buffers: Vec<OutputBuffer>,
+    /// indices into `output_buffers` of submitted frames, oldest first
+    in_flight: VecDeque<usize>,
+    stats: FrameStats,
+    last_completed: Option<Instant>,
+
+    render_pipeline: wgpu::RenderPipeline,
+
+    layers: Vec<LayerState>,
+    format: OutputFormat,
+
+    instance_buffer: wgpu::Buffer,
+    monitors: Vec<Monitor>,
+    active: Vec<bool>,
+    bezel: [f32; 2],
+}
+
+impl<'a> Renderer<'a> {
+    /// A renderer for `scene` on a new device; see `RendererBuilder` for more control.
+    pub async fn new(
+        size: [u16; 2],
+        monitors: &[Monitor],
+        scene: &Scene,
+        format: OutputFormat,
+    ) -> Result<Renderer<'a>> {
+        RendererBuilder::new(size)
+            .monitors(monitors)
+            .format(format)
+            .scene(scene)?
+            .build()
+            .await
+    }
+
+    /// Moves the layer quads to where they are at time `t`.
+    fn write_vertices(&self, t: Duration) {
+        let vbuf = self.layers.iter().flat_map(|layer| {
+            let offset = layer.layout.motion.offset(t);
+            let size = layer.layout.size;
+            VERTICES.iter().map(move |v| {
+                let mut v = *v;
+                if let Some(size) = size {
+                    // the shader moves the corners out from the center
+                    v.position = [0.5, 0.5, 0.0];
+                    v.extent = size;
+                }
+                v.position[0] += offset[0];
+                v.position[1] += offset[1];
+                v
+            })
+        }).collect::<Vec<_>>();
+
+        self.queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vbuf));
+    }
+
+    /// Renders the frame at time `t` as seen by monitor number `monitor` into `view`, e.g.
+    /// the surface of a window covering that monitor. The view must have the texture format
+    /// of `format()`. Nothing is read back.
+    pub fn render_to(&mut self, t: Duration, monitor: usize, view: &wgpu::TextureView) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("encoder"),
+        });
+        self.write_vertices(t);
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(self.format.clear_color(BACKGROUND)),
+                            store: true,
+                        },
+                    })
+                ],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            for (i, layer) in self.layers.iter().enumerate() {
+                let first = i as u32 * 4;
+                let instance = self.instance(layer.layout.placement, monitor, true);
+                render_pass.set_bind_group(0, &layer.bind_group, &[]);
+                render_pass.draw(first..first + 4, instance..instance + 1);
+            }
         }
+        self.queue.submit(Some(encoder.finish()));
     }
 
+    /// Renders the frame at time `t` and reads it back synchronously.
+    /// Frames still in flight from `submit` are dropped. Fails with `Error::Readback` if the
+    /// frame can't be read, e.g. after the device was lost.
     pub async fn render<T>( &mut self,
         t: std::time::Duration,
         callback: impl FnOnce(wgpu::BufferView) -> T
-    ) -> Result<T, wgpu::SurfaceError> {
+    ) -> Result<T> {
+        if !self.submit(t) {
+            self.drop_oldest();
+            self.submit(t);
+        }
+
+        // NOTE: We have to create the mapping THEN device.poll() before await
+        // the future. Otherwise the application will freeze.
+        self.device.poll(wgpu::Maintain::Wait);
+        while self.in_flight.len() > 1 {
+            self.drop_oldest();
+        }
+        let index = self.in_flight.pop_front().unwrap();
+        let rx = self.output_buffers[index].mapped.take().unwrap();
+        map_result(rx.recv())?;
+
+        Ok(self.complete(index, callback))
+    }
+
+    /// Starts rendering the frame at time `t` without waiting for it.
+    /// Returns false if all readback buffers are still in use, in which case nothing is done.
+    pub fn submit(&mut self, t: std::time::Duration) -> bool {
+        let Some(index) = (0..self.output_buffers.len()).find(|i| !self.in_flight.contains(i)) else {
+            return false;
+        };
+
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("encoder"),
         });
 
-        let u32_size = std::mem::size_of::<u32>() as u32;
         {
             let render_pass_desc = wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -317,12 +719,12 @@ impl<'a> Renderer<'a> {
                         view: &self.texture_view,
                         resolve_target: None,
                         ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.1,
-                                g: 0.2,
-                                b: 0.3,
-                                a: 1.0,
-                            }),
+                            // keep the last frame of paused monitors
+                            load: if self.active.iter().all(|&a| a) {
+                                wgpu::LoadOp::Clear(self.format.clear_color(BACKGROUND))
+                            } else {
+                                wgpu::LoadOp::Load
+                            },
                             store: true,
                         },
                     })
@@ -333,13 +735,7 @@ impl<'a> Renderer<'a> {
             {
                 let mut render_pass = encoder.begin_render_pass(&render_pass_desc);
 
-                let mut vbuf: [Vertex; 8] = unsafe { std::mem::uninitialized() };
-                vbuf.copy_from_slice(&VERTICES);
-                for i in 4..8 {
-                    vbuf[i].position[1] += t.as_secs_f32().cos() * 0.1;
-                }
-
-                self.queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vbuf));
+                self.write_vertices(t);
 
 
                 // self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&[
@@ -348,12 +744,22 @@ impl<'a> Renderer<'a> {
                 // ]));
 
                 render_pass.set_pipeline(&self.render_pipeline);
-                render_pass.set_bind_group(0, &self.diffuse0_bind_group, &[]);
-                render_pass.set_bind_group(1, &self.diffuse1_bind_group, &[]);
                 render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
                 render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                render_pass.draw(0..4, 0..self.instance_len as u32);
-                render_pass.draw(4..8, 0..self.instance_len as u32);
+                for (i, layer) in self.layers.iter().enumerate() {
+                    let first = i as u32 * 4;
+                    render_pass.set_bind_group(0, &layer.bind_group, &[]);
+                    for (j, m) in self.monitors.iter().enumerate() {
+                        let [x, y, w, h] = m.rect(self.size());
+                        if !self.active[j] || w == 0 || h == 0 {
+                            continue;
+                        }
+                        // moving layers must not spill over onto paused neighbours
+                        render_pass.set_scissor_rect(x.into(), y.into(), w.into(), h.into());
+                        let instance = self.instance(layer.layout.placement, j, false);
+                        render_pass.draw(first..first + 4, instance..instance + 1);
+                    }
+                }
             }
 
             encoder.copy_texture_to_buffer(
@@ -364,10 +770,10 @@ impl<'a> Renderer<'a> {
                     origin: wgpu::Origin3d::ZERO,
                 },
                 wgpu::ImageCopyBuffer {
-                    buffer: &self.output_buffer,
+                    buffer: &self.output_buffers[index].buffer,
                     layout: wgpu::ImageDataLayout {
                         offset: 0,
-                        bytes_per_row: Some(u32_size * self.get_width()),
+                        bytes_per_row: Some(self.row_layout().padded_bytes_per_row as u32),
                         rows_per_image: Some(self.get_height()),
                     },
                 },
@@ -378,23 +784,155 @@ impl<'a> Renderer<'a> {
             self.queue.submit(Some(encoder.finish()));
         }
 
-        let buffer_slice = self.output_buffer.slice(..);
-
-        // NOTE: We have to create the mapping THEN device.poll() before await
-        // the future. Otherwise the application will freeze.
-        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
-        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-            tx.send(result).unwrap();
+        let output = &mut self.output_buffers[index];
+        let (tx, rx) = std::sync::mpsc::channel();
+        output.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            // the receiver is gone if the frame was dropped
+            let _ = tx.send(result);
         });
+        output.submitted = Some(Instant::now());
+        output.mapped = Some(rx);
+        self.in_flight.push_back(index);
+
+        true
+    }
+
+    /// Hands the newest finished frame to `callback` without blocking, if there is one.
+    /// Older finished frames are skipped. Fails with `Error::Readback` if a frame can't be
+    /// read, e.g. after the device was lost.
+    pub fn try_read<T>(&mut self, callback: impl FnOnce(wgpu::BufferView) -> T) -> Result<Option<T>> {
+        self.device.poll(wgpu::Maintain::Poll);
+
+        // frames finish in submission order
+        let mut ready: Option<usize> = None;
+        while let Some(&index) = self.in_flight.front() {
+            let result = match self.output_buffers[index].mapped.as_ref().unwrap().try_recv() {
+                Ok(result) => Ok(result),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => Err(mpsc::RecvError),
+            };
+            self.in_flight.pop_front();
+            self.output_buffers[index].mapped = None;
+            if let Err(e) = map_result(result) {
+                if let Some(prev) = ready {
+                    self.output_buffers[prev].buffer.unmap();
+                }
+                return Err(e);
+            }
+            if let Some(prev) = ready.replace(index) {
+                self.output_buffers[prev].buffer.unmap();
+                self.stats.dropped += 1;
+            }
+        }
+
+        Ok(ready.map(|index| self.complete(index, callback)))
+    }
+
+    /// Frame counters and timings of the readback pipeline.
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    fn complete<T>(&mut self, index: usize, callback: impl FnOnce(wgpu::BufferView) -> T) -> T {
+        let now = Instant::now();
+        let output = &mut self.output_buffers[index];
+
+        let latency = now - output.submitted.take().unwrap();
+        self.stats.frames += 1;
+        self.stats.latency = self.stats.latency.mul_f32(1.0 - STATS_SMOOTHING) + latency.mul_f32(STATS_SMOOTHING);
+        if let Some(last) = self.last_completed.replace(now) {
+            let fps = 1.0 / (now - last).as_secs_f32().max(f32::EPSILON);
+            self.stats.throughput = self.stats.throughput * (1.0 - STATS_SMOOTHING) + fps * STATS_SMOOTHING;
+        }
+
+        let ret = {
+            let data = output.buffer.slice(..).get_mapped_range();
+            callback(data)
+        };
+        output.buffer.unmap();
+        ret
+    }
+
+    /// Gives up on the oldest frame in flight, waiting for the gpu if necessary.
+    fn drop_oldest(&mut self) {
+        let Some(index) = self.in_flight.pop_front() else { return };
+        let output = &mut self.output_buffers[index];
+        self.device.poll(wgpu::Maintain::Wait);
+        if let Some(rx) = output.mapped.take() {
+            if let Ok(Ok(())) = rx.recv() {
+                output.buffer.unmap();
+            }
+        }
+        output.submitted = None;
+        self.stats.dropped += 1;
+    }
+
+    /// Adapts to a new screen size and monitor layout.
+    /// Frames in flight are dropped and all monitors become active.
+    pub fn resize(&mut self, size: [u16; 2], monitors: &[Monitor]) {
         self.device.poll(wgpu::Maintain::Wait);
-        rx.receive().await.unwrap().unwrap();
+        while !self.in_flight.is_empty() {
+            self.drop_oldest();
+        }
+
+        self.texture_desc.size = wgpu::Extent3d {
+            width: size[0].into(),
+            height: size[1].into(),
+            depth_or_array_layers: 1,
+        };
+        self.texture = self.device.create_texture(&self.texture_desc);
+        self.texture_view = self.texture.create_view(&Default::default());
+
+        self.output_buffers = create_output_buffers(&self.device, size, self.output_buffers.len());
+        self.instance_buffer = create_instance_buffer(&self.device, size, monitors, self.bezel);
+        self.monitors = monitors.to_vec();
+        self.active = vec![true; monitors.len()];
+    }
+
+    /// Selects which monitors are redrawn; paused monitors keep their last frame.
+    pub fn set_active(&mut self, active: &[bool]) {
+        self.active.copy_from_slice(active);
+    }
+
+    /// True if no monitor needs to be redrawn.
+    pub fn is_paused(&self) -> bool {
+        !self.active.iter().any(|&a| a)
+    }
+
+    /// True if every frame is identical, so rendering once is enough.
+    pub fn is_static(&self) -> bool {
+        self.layers.iter().all(|l| l.layout.motion.is_static())
+    }
+
+    /// Layout of the frames handed to `render` and `try_read` callbacks.
+    pub fn row_layout(&self) -> RowLayout {
+        RowLayout::new(self.get_width(), self.get_height())
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
 
-        let data = buffer_slice.get_mapped_range();
-        let ret = callback(data);
+    /// The device frames are rendered on, e.g. to share it with another `RendererBuilder`.
+    pub fn device(&self) -> (&Arc<wgpu::Device>, &Arc<wgpu::Queue>) {
+        (&self.device, &self.queue)
+    }
 
-        self.output_buffer.unmap();
+    /// Index into the instance buffer for drawing a layer placed as given onto `monitor`,
+    /// or onto a target of its own with `full`.
+    fn instance(&self, placement: Placement, monitor: usize, full: bool) -> u32 {
+        let n = self.monitors.len();
+        let group = match (placement, full) {
+            (Placement::Monitor, false) => 0,
+            (Placement::Monitor, true) => 1,
+            (Placement::Span, false) => 2,
+            (Placement::Span, true) => 3,
+        };
+        (group * n + monitor) as u32
+    }
 
-        Ok(ret)
+    fn size(&self) -> [u16; 2] {
+        [self.get_width() as u16, self.get_height() as u16]
     }
 
     pub fn get_width(&self) -> u32 {
@@ -404,3 +942,90 @@ impl<'a> Renderer<'a> {
         self.texture_desc.size.height
     }
 }
+
+fn create_output_buffers(device: &wgpu::Device, size: [u16; 2], count: usize) -> Vec<OutputBuffer> {
+    let rows = RowLayout::new(size[0].into(), size[1].into());
+    let output_buffer_size = (rows.padded_bytes_per_row * rows.rows) as wgpu::BufferAddress;
+    let output_buffer_desc = wgpu::BufferDescriptor {
+        size: output_buffer_size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        label: Some("output_buffer"),
+        mapped_at_creation: false,
+    };
+    (0..count).map(|_| OutputBuffer {
+        buffer: device.create_buffer(&output_buffer_desc),
+        submitted: None,
+        mapped: None,
+    }).collect()
+}
+
+/// Outcome of mapping a readback buffer, as received from the `map_async` callback.
+fn map_result(received: Result<Result<(), wgpu::BufferAsyncError>, mpsc::RecvError>) -> Result<()> {
+    match received {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(Error::Readback(e.to_string())),
+        // wgpu drops the callback without calling it when the device is gone
+        Err(mpsc::RecvError) => Err(Error::Readback("device lost".into())),
+    }
+}
+
+/// Instances in four groups of one per monitor, see `Renderer::instance`: each monitor in
+/// clip space, then covering the whole target for `render_to`, and both again showing the
+/// monitor's part of spanned layers.
+fn create_instance_buffer(device: &wgpu::Device, size: [u16; 2], monitors: &[Monitor], bezel: [f32; 2]) -> wgpu::Buffer {
+    let (width, height) = (size[0] as f32, size[1] as f32);
+    let on_monitor = |m: &Monitor| (
+        [
+            m.x as f32 / width * 2.0 - 1.0,
+            1.0 - (m.y as f32 + m.height as f32) / height * 2.0,
+        ],
+        [
+            m.width as f32 / width * 2.0,
+            m.height as f32 / height * 2.0,
+        ],
+    );
+    let full = ([-1.0, -1.0], [2.0, 2.0]);
+
+    let logical_px = |m: &Monitor| {
+        let scale = m.scale();
+        [scale / m.width.max(1) as f32, scale / m.height.max(1) as f32]
+    };
+
+    // the whole image; the server turns it upright for rotated panels
+    let whole = |m: &Monitor, (position, size)| {
+        Instance {
+            position,
+            size,
+            tex_u: [1.0, 0.0],
+            tex_v: [0.0, 1.0],
+            tex_center: [0.5, 0.5],
+            logical_px: logical_px(m),
+        }
+    };
+    // part of an image spread over all monitors, upright as the root window
+    let spanned = |&[x, y, w, h]: &[f32; 4], m: &Monitor, (position, size)| {
+        Instance {
+            position,
+            size,
+            tex_u: [w, 0.0],
+            tex_v: [0.0, h],
+            tex_center: [x + w / 2.0, y + h / 2.0],
+            logical_px: logical_px(m),
+        }
+    };
+
+    let span = crate::monitor::span(monitors, bezel);
+    let mut instances = Vec::with_capacity(monitors.len() * 4);
+    instances.extend(monitors.iter().map(|m| whole(m, on_monitor(m))));
+    instances.extend(monitors.iter().map(|m| whole(m, full)));
+    instances.extend(span.iter().zip(monitors).map(|(s, m)| spanned(s, m, on_monitor(m))));
+    instances.extend(span.iter().zip(monitors).map(|(s, m)| spanned(s, m, full)));
+
+    device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("Instance Buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        }
+    )
+}
@@ -0,0 +1,217 @@
+//! The root window as an output: frames become the wallpaper, and are announced to
+//! compositors and pseudo transparent clients through `_XROOTPMAP_ID`.
+
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    AtomEnum,
+    ChangeWindowAttributesAux,
+    CloseDown,
+    ConnectionExt,
+    CreateGCAux,
+    Gcontext,
+    PropMode,
+    Window,
+};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+use crate::error::Result;
+use crate::format::PixelFormat;
+use crate::output::{Frame, OutputBackend};
+use crate::pixmap::FramePixmap;
+
+pub struct RootWindow<'c> {
+    conn: &'c RustConnection,
+    root: Window,
+    gc: Gcontext,
+    width: u16,
+    height: u16,
+    depth: u8,
+    prop_root: u32,
+    prop_esetroot: u32,
+
+    pm: FramePixmap,
+    /// pixmap of the previous layout, freed once a frame in the new one is shown
+    retired: Option<FramePixmap>,
+
+    /// a compositor paints the root window from `_XROOTPMAP_ID`; we only update the pixmap
+    compositing: bool,
+    /// set when the root window no longer shows the current frame
+    stale: bool,
+}
+
+impl<'c> RootWindow<'c> {
+    /// Takes over the wallpaper of `screen`, freeing the previous setter's pixmap.
+    pub fn new(conn: &'c RustConnection, screen: usize, size: [u16; 2]) -> Result<Self> {
+        let screen = &conn.setup().roots[screen];
+        let root = screen.root;
+
+        let format = PixelFormat::from_setup(conn.setup(), screen)?;
+
+        // falls back to core requests if shared memory is unavailable
+        let pm = FramePixmap::new(conn, root, size[0], size[1], format)?;
+
+        let gc = conn.generate_id()?;
+        let gc_aux = CreateGCAux::new()
+            .background(screen.white_pixel)
+            .foreground(0xffff0000);
+        conn.create_gc(gc, root, &gc_aux)?;
+
+        let prop_root = conn.intern_atom(false, b"_XROOTPMAP_ID")?.reply()?.atom;
+        let prop_esetroot = conn.intern_atom(false, b"ESETROOT_PMAP_ID")?.reply()?.atom;
+
+        let output = Self {
+            conn,
+            root,
+            gc,
+            width: size[0],
+            height: size[1],
+            depth: screen.root_depth,
+            prop_root,
+            prop_esetroot,
+            pm,
+            retired: None,
+            compositing: false,
+            stale: false,
+        };
+        output.kill_previous()?;
+        output.publish(output.pm.pixmap())?;
+        Ok(output)
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pm.format()
+    }
+
+    /// True if frames travel through shared memory, i.e. the server is local.
+    pub fn is_shm(&self) -> bool {
+        self.pm.is_shm()
+    }
+
+    /// Switches between publishing strategies. With a compositor, frames only go into the
+    /// pixmap, and rewriting `_XROOTPMAP_ID` makes the compositor pick them up. Without one,
+    /// frames are drawn onto the root window, and the property is left alone.
+    pub fn set_compositing(&mut self, compositing: bool) {
+        if self.compositing && !compositing {
+            // nothing drew onto the root window meanwhile
+            self.stale = true;
+        }
+        self.compositing = compositing;
+    }
+
+    /// Copies the last frame into a plain server-side pixmap and makes it the wallpaper,
+    /// so that nothing refers to our shared memory after we exit.
+    pub fn publish_static(self) -> Result<()> {
+        let conn = self.conn;
+        let pixmap = conn.generate_id()?;
+        conn.create_pixmap(self.depth, pixmap, self.root, self.width, self.height)?;
+        conn.copy_area(self.pm.pixmap(), pixmap, self.gc, 0, 0, 0, 0, self.width, self.height)?;
+
+        self.publish(pixmap)?;
+        conn.clear_area(false, self.root, 0, 0, 0, 0)?;
+
+        self.free_pixmaps()?;
+        conn.sync()?;
+        Ok(())
+    }
+
+    /// Cleans up before exiting. With `keep`, the last frame stays as a plain pixmap;
+    /// otherwise the wallpaper is removed and the server frees everything we created.
+    pub fn shutdown(self, keep: bool) -> Result<()> {
+        if keep {
+            return self.publish_static();
+        }
+        let conn = self.conn;
+
+        for prop in [self.prop_root, self.prop_esetroot] {
+            conn.delete_property(self.root, prop)?;
+        }
+        conn.change_window_attributes(self.root, &ChangeWindowAttributesAux::new().background_pixmap(x11rb::NONE))?;
+        conn.clear_area(false, self.root, 0, 0, 0, 0)?;
+
+        self.free_pixmaps()?;
+        conn.set_close_down_mode(CloseDown::DESTROY_ALL)?;
+        conn.sync()?;
+        Ok(())
+    }
+
+    fn free_pixmaps(self) -> Result<()> {
+        if let Some(old) = self.retired {
+            old.free(self.conn)?;
+        }
+        self.pm.free(self.conn)?;
+        Ok(())
+    }
+
+    /// Frees the pixmap left behind by the previous wallpaper setter, following the Esetroot
+    /// convention: if `ESETROOT_PMAP_ID` and `_XROOTPMAP_ID` agree, the setter retained its
+    /// resources and killing the pixmap's client releases them.
+    fn kill_previous(&self) -> Result<()> {
+        let read = |prop| -> Result<Option<u32>> {
+            let reply = self.conn.get_property(false, self.root, prop, AtomEnum::PIXMAP, 0, 1)?.reply()?;
+            Ok(reply.value32().and_then(|mut v| v.next()))
+        };
+        let (Some(root_pixmap), Some(esetroot_pixmap)) = (read(self.prop_root)?, read(self.prop_esetroot)?) else { return Ok(()) };
+        if root_pixmap == esetroot_pixmap && root_pixmap != x11rb::NONE {
+            // the client may already be gone
+            self.conn.kill_client(root_pixmap)?.ignore_error();
+        }
+        Ok(())
+    }
+
+    /// Makes `pixmap` the root background and announces it to compositors and pseudo
+    /// transparent clients.
+    fn publish(&self, pixmap: u32) -> Result<()> {
+        for prop in [self.prop_root, self.prop_esetroot] {
+            self.conn.change_property32(PropMode::REPLACE, self.root, prop, AtomEnum::PIXMAP, &[pixmap])?;
+        }
+        self.conn.change_window_attributes(self.root, &ChangeWindowAttributesAux::new().background_pixmap(pixmap))?;
+        Ok(())
+    }
+}
+
+impl OutputBackend for RootWindow<'_> {
+    /// The new pixmap replaces the old one only once a frame has been drawn into it, so that
+    /// nothing flickers.
+    fn configure(&mut self, size: [u16; 2], _monitors: &[[u16; 4]]) -> Result<()> {
+        let old = self.pm.resize(self.conn, size[0], size[1])?;
+        if let Some(older) = self.retired.replace(old) {
+            older.free(self.conn)?;
+        }
+        [self.width, self.height] = size;
+        Ok(())
+    }
+
+    /// Uploads the damaged parts of `frame`, and either draws them onto the root window or
+    /// notifies the compositor.
+    fn present(&mut self, frame: &Frame) -> Result<()> {
+        let format = self.pm.format();
+        format.write_frame(frame.format, frame.rows, frame.data, self.pm.as_slice());
+
+        let full = [[0, 0, self.width, self.height]];
+        let rects = if self.retired.is_some() || self.stale { &full[..] } else { frame.damage };
+        if self.compositing {
+            self.pm.upload(self.conn, self.gc, rects)?;
+        } else {
+            self.pm.draw(self.conn, self.gc, self.root, rects)?;
+            self.stale = false;
+        }
+
+        if let Some(old) = self.retired.take() {
+            self.publish(self.pm.pixmap())?;
+            old.free(self.conn)?;
+        } else if self.compositing {
+            // the compositor re-reads the pixmap when the property changes
+            self.conn.change_property32(
+                PropMode::REPLACE,
+                self.root,
+                self.prop_root,
+                AtomEnum::PIXMAP,
+                &[self.pm.pixmap()],
+            )?;
+        }
+
+        self.conn.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+
+/// Where a layer's image comes from.
+#[derive(Debug, Clone)]
+pub enum ImageSource {
+    Path(PathBuf),
+    /// image compiled into the binary; used by the built-in demo scene
+    Embedded(&'static str, &'static [u8]),
+}
+
+// scene files can only refer to images by path
+impl<'de> Deserialize<'de> for ImageSource {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        PathBuf::deserialize(deserializer).map(ImageSource::Path)
+    }
+}
+
+impl ImageSource {
+    pub fn label(&self) -> String {
+        match self {
+            ImageSource::Path(path) => path.display().to_string(),
+            ImageSource::Embedded(name, _) => name.to_string(),
+        }
+    }
+
+    pub fn load(&self) -> Result<image::DynamicImage> {
+        match self {
+            ImageSource::Path(path) => image::open(path),
+            ImageSource::Embedded(_, bytes) => image::load_from_memory(bytes),
+        }.map_err(|source| Error::Image { label: self.label(), source })
+    }
+}
+
+/// How a layer moves over time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Motion {
+    #[default]
+    None,
+    /// vertical oscillation; `amplitude` is a fraction of the monitor height
+    Bob {
+        amplitude: f32,
+        #[serde(default = "default_speed")]
+        speed: f32,
+    },
+}
+
+fn default_speed() -> f32 { 1.0 }
+
+impl Motion {
+    pub fn is_static(&self) -> bool {
+        matches!(self, Motion::None)
+    }
+
+    /// offset of the layer quad at time `t`
+    pub fn offset(&self, t: std::time::Duration) -> [f32; 2] {
+        match *self {
+            Motion::None => [0.0, 0.0],
+            Motion::Bob { amplitude, speed } => [0.0, (t.as_secs_f32() * speed).cos() * amplitude],
+        }
+    }
+}
+
+/// How a layer is laid out over the monitors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Placement {
+    /// a copy on every monitor
+    #[default]
+    Monitor,
+    /// one image over all monitors, following their physical size and arrangement
+    Span,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Layer {
+    pub image: ImageSource,
+    #[serde(default)]
+    pub motion: Motion,
+    #[serde(default)]
+    pub placement: Placement,
+    /// size in logical pixels, centered on each monitor and scaled by its scale factor;
+    /// stretched over the monitor if unset
+    #[serde(default)]
+    pub size: Option<[f32; 2]>,
+}
+
+/// Layers drawn on every monitor, bottom first.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scene {
+    #[serde(rename = "layer")]
+    pub layers: Vec<Layer>,
+    /// gap between neighbouring panels in mm, horizontally and vertically, for spanned
+    /// layers: the bezels of both panels plus any space between them
+    #[serde(default, deserialize_with = "deserialize_bezel")]
+    pub bezel: [f32; 2],
+}
+
+fn deserialize_bezel<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<[f32; 2], D::Error> {
+    let bezel = <[f32; 2]>::deserialize(deserializer)?;
+    if !bezel.iter().all(|gap| *gap >= 0.0 && gap.is_finite()) {
+        return Err(serde::de::Error::custom("bezel gaps must be finite and not negative"));
+    }
+    Ok(bezel)
+}
+
+impl Scene {
+    /// Loads a scene from a toml file.
+    /// Relative image paths are resolved against the directory of the file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|source| Error::Io { path: path.to_path_buf(), source })?;
+        let mut scene: Scene = toml::from_str(&text)
+            .map_err(|source| Error::Parse { path: path.to_path_buf(), source })?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        for layer in &mut scene.layers {
+            if let ImageSource::Path(p) = &mut layer.image {
+                if p.is_relative() {
+                    *p = base.join(&p);
+                }
+            }
+        }
+        Ok(scene)
+    }
+
+    /// A single static image stretched over each monitor, or spread over all of them with
+    /// `Placement::Span`.
+    pub fn from_image(path: PathBuf, placement: Placement) -> Self {
+        Self {
+            layers: vec![Layer { image: ImageSource::Path(path), motion: Motion::None, placement, size: None }],
+            bezel: [0.0; 2],
+        }
+    }
+
+    /// The built-in demo scene.
+    pub fn demo() -> Self {
+        Self {
+            layers: vec![
+                Layer {
+                    image: ImageSource::Embedded("happy-tree.png", include_bytes!("happy-tree.png")),
+                    motion: Motion::None,
+                    placement: Placement::Monitor,
+                    size: None,
+                },
+                Layer {
+                    image: ImageSource::Embedded("favicon.png", include_bytes!("favicon.png")),
+                    motion: Motion::Bob { amplitude: 0.1, speed: 1.0 },
+                    placement: Placement::Monitor,
+                    size: None,
+                },
+            ],
+            bezel: [0.0; 2],
+        }
+    }
+
+    /// True if no layer depends on time, i.e. one frame is enough.
+    pub fn is_static(&self) -> bool {
+        self.layers.iter().all(|l| l.motion.is_static())
+    }
+}
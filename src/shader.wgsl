@@ -3,18 +3,25 @@
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
-    @location(2) tex_idx: u32,
+    // size in logical pixels of layers drawn at a fixed size; zero otherwise
+    @location(2) extent: vec2<f32>,
 };
 
 struct InstanceInput {
     @location(3) pos: vec2<f32>,
     @location(4) size: vec2<f32>,
+    // scale of the texture on the monitor; rows of a matrix around the center
+    @location(5) tex_u: vec2<f32>,
+    @location(6) tex_v: vec2<f32>,
+    // center of the part of the texture shown
+    @location(7) tex_center: vec2<f32>,
+    // fraction of the quad per logical pixel
+    @location(8) logical_px: vec2<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
-    @location(1) tex_idx: u32,
 };
 
 @vertex
@@ -23,27 +30,34 @@ fn vs_main(
     instance: InstanceInput,
 ) -> VertexOutput {
     var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    out.tex_idx = model.tex_idx;
-    out.clip_position = vec4<f32>(instance.pos, 0.0, 0.0) + vec4<f32>(model.position, 1.0) * vec4<f32>(instance.size, 1.0, 1.0);
+    let c = model.tex_coords - vec2<f32>(0.5, 0.5);
+    out.tex_coords = vec2<f32>(dot(instance.tex_u, c), dot(instance.tex_v, c)) + instance.tex_center;
+    // corners of fixed size layers
+    let corner = vec2<f32>(c.x, -c.y) * model.extent * instance.logical_px;
+    let position = model.position + vec3<f32>(corner, 0.0);
+    out.clip_position = vec4<f32>(instance.pos, 0.0, 0.0) + vec4<f32>(position, 1.0) * vec4<f32>(instance.size, 1.0, 1.0);
     return out;
 }
 
 // Fragment shader
 
 @group(0)@binding(0)
-var d0_t: texture_2d<f32>;
+var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
-var d0_s: sampler;
-
-@group(1)@binding(0)
-var d1_t: texture_2d<f32>;
-@group(1)@binding(1)
-var d1_s: sampler;
+var s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var t0c = textureSample(d0_t, d0_s, in.tex_coords);
-    var t1c = textureSample(d1_t, d1_s, in.tex_coords);
-    return select(t0c, t1c, in.tex_idx > 0u);
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+}
+
+fn srgb_encode(c: vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+// for non-srgb 10 bit targets: encode manually and swap red and blue
+@fragment
+fn fs_main_bgr10(in: VertexOutput) -> @location(0) vec4<f32> {
+    var c = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return vec4<f32>(srgb_encode(c.rgb).bgr, c.a);
 }
@@ -1,66 +1,85 @@
 use x11rb::connection::{Connection, RequestConnection};
 use x11rb::protocol::shm::ConnectionExt as ShmConnectionExt;
 use x11rb::protocol::xproto::ConnectionExt as XpConnectionExt;
-use libc::{shmat, shmdt, shmget, IPC_CREAT, IPC_PRIVATE};
+use libc::{shmat, shmctl, shmdt, shmget, IPC_CREAT, IPC_PRIVATE, IPC_RMID};
 use std::ptr;
 
+use crate::error::{Error, Result};
+use crate::format::PixelFormat;
+
 pub struct ShmSegWrapper {
     pub seg: u32,
+    pub shmid: i32,
     pub shm_addr: *mut u8,
     pub size: usize,
 }
 
 impl ShmSegWrapper {
 
+    /// Fails if the server can't attach the segment, e.g. because it runs on another machine.
     pub fn new(connection: &x11rb::rust_connection::RustConnection, size: usize)
-        -> Result<Self, x11rb::errors::ConnectionError> {
+        -> Result<Self> {
 
         if connection
             .extension_information(x11rb::protocol::shm::X11_EXTENSION_NAME)?
             .is_none() {
-                return Err(x11rb::errors::ConnectionError::UnsupportedExtension);
+                return Err(Error::MissingExtension(x11rb::protocol::shm::X11_EXTENSION_NAME));
         }
 
-        let seg = connection.generate_id().unwrap();
+        let seg = connection.generate_id()?;
 
-        let shm_addr = unsafe {
-            let shmid = shmget(
+        let shmid = unsafe {
+            shmget(
                 IPC_PRIVATE,
                 size,
                 IPC_CREAT | 0o777,
-            );
+            )
+        };
 
-            if shmid < 0 {
-                panic!("shmget failed");
-            }
+        if shmid < 0 {
+            return Err(Error::Shm(std::io::Error::last_os_error()));
+        }
 
+        let shm_addr = unsafe {
             let shm_addr = shmat(shmid, ptr::null_mut(), 0);
 
             if shm_addr == -1isize as *mut _ {
-                dbg!(std::io::Error::last_os_error().raw_os_error().unwrap());
-                panic!("shmat failed");
+                let e = std::io::Error::last_os_error();
+                shmctl(shmid, IPC_RMID, ptr::null_mut());
+                return Err(Error::Shm(e));
             }
 
-            dbg!(
-                shmid,
-                shm_addr,
-                size
-            );
+            // the server may see a different shm namespace (ssh -X, containers); find out now
+            if let Err(e) = connection.shm_attach(seg, shmid as u32, false)?.check() {
+                shmdt(shm_addr);
+                shmctl(shmid, IPC_RMID, ptr::null_mut());
+                return Err(e.into());
+            }
 
-            connection.shm_attach(seg, shmid as u32, false)?;
+            // both sides are attached now; the segment disappears once both detach, even if
+            // we are killed without cleaning up
+            shmctl(shmid, IPC_RMID, ptr::null_mut());
 
             shm_addr
         };
 
         Ok(Self {
             seg,
+            shmid,
             shm_addr: shm_addr as *mut u8,
             size,
         })
 
     }
 
-    pub fn as_slice(&self) -> &mut [u8] {
+    /// Detaches the server from the segment. It is removed once we detach as well (on drop).
+    pub fn free(self, connection: &x11rb::rust_connection::RustConnection)
+        -> Result<(), x11rb::errors::ConnectionError> {
+        connection.shm_detach(self.seg)?;
+        Ok(())
+    }
+
+    pub fn as_slice(&mut self) -> &mut [u8] {
         unsafe {
             std::slice::from_raw_parts_mut(self.shm_addr, self.size)
         }
@@ -81,6 +100,7 @@ pub struct ShmPixmap {
 
     pub width: u16,
     pub height: u16,
+    pub format: PixelFormat,
 
     pub drawable: x11rb::protocol::xproto::Drawable,
 }
@@ -91,11 +111,12 @@ impl ShmPixmap {
         drawable: x11rb::protocol::xproto::Drawable,
         width: u16,
         height: u16,
-        ) -> Result<Self, x11rb::errors::ConnectionError> {
+        format: PixelFormat,
+        ) -> Result<Self> {
 
-        let shmseg = ShmSegWrapper::new(connection, width as usize * height as usize * 4)?;
+        let shmseg = ShmSegWrapper::new(connection, format.stride(width) * height as usize)?;
 
-        let pixmap = connection.generate_id().unwrap();
+        let pixmap = connection.generate_id()?;
 
         // connection.create_pixmap(
         //     32,
@@ -110,7 +131,7 @@ impl ShmPixmap {
             drawable,
             width,
             height,
-            32,
+            format.depth,
             shmseg.seg,
             0,
         )?;
@@ -121,31 +142,25 @@ impl ShmPixmap {
 
             width,
             height,
+            format,
 
             drawable,
         })
     }
 
-    pub fn resize(&mut self, connection: &x11rb::rust_connection::RustConnection, width: u16, height: u16) -> Result<(), x11rb::errors::ConnectionError> {
-
-        connection.free_pixmap(self.pixmap).unwrap();
-
-        self.shmseg = ShmSegWrapper::new(connection, width as usize * height as usize * 4)?;
-
-        self.width = width;
-        self.height = height;
-
-        // self.pixmap = connection.generate_id().unwrap();
-        connection.shm_create_pixmap(
-            self.pixmap,
-            self.drawable,
-            width,
-            height,
-            32,
-            self.shmseg.seg,
-            0,
-        )?;
-        Ok(())
+    /// Replaces the pixmap with a new one of the given size.
+    /// The previous pixmap is returned and stays valid until it is `free`d, so that it can be
+    /// swapped out without flicker.
+    pub fn resize(&mut self, connection: &x11rb::rust_connection::RustConnection, width: u16, height: u16)
+        -> Result<ShmPixmap> {
+        let new = ShmPixmap::new(connection, self.drawable, width, height, self.format)?;
+        Ok(std::mem::replace(self, new))
     }
 
+    /// Frees the pixmap and its shared memory.
+    pub fn free(self, connection: &x11rb::rust_connection::RustConnection)
+        -> Result<(), x11rb::errors::ConnectionError> {
+        connection.free_pixmap(self.pixmap)?;
+        self.shmseg.free(connection)
+    }
 }
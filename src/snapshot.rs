@@ -0,0 +1,39 @@
+//! Rendered frames as image files, for rendering without an X server.
+
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::render::{OutputFormat, RowLayout};
+
+/// Converts a frame read back from the gpu into an RGBA image.
+pub fn to_rgba(format: OutputFormat, rows: RowLayout, src: &[u8]) -> image::RgbaImage {
+    let width = (rows.bytes_per_row / 4) as u32;
+    let mut data = Vec::with_capacity(rows.bytes_per_row * rows.rows);
+    for row in src.chunks(rows.padded_bytes_per_row).take(rows.rows) {
+        for p in row[..rows.bytes_per_row].chunks_exact(4) {
+            match format {
+                OutputFormat::Bgra8 => data.extend_from_slice(&[p[2], p[1], p[0], 0xff]),
+                OutputFormat::Bgr10 => {
+                    // keep the high 8 of each 10 bits
+                    let v = u32::from_le_bytes([p[0], p[1], p[2], p[3]]);
+                    data.extend_from_slice(&[(v >> 22) as u8, (v >> 12) as u8, (v >> 2) as u8, 0xff]);
+                }
+            }
+        }
+    }
+    image::RgbaImage::from_raw(width, rows.rows as u32, data).unwrap()
+}
+
+/// Writes `img` to `path`; the format follows the extension (`.png`, `.ppm`, ...).
+pub fn save(img: &image::RgbaImage, path: &Path) -> Result<()> {
+    let label = || path.display().to_string();
+    let format = image::ImageFormat::from_path(path)
+        .map_err(|source| Error::Image { label: label(), source })?;
+    // ppm has no alpha channel
+    let result = if format == image::ImageFormat::Pnm {
+        image::DynamicImage::ImageRgba8(img.clone()).into_rgb8().save_with_format(path, format)
+    } else {
+        img.save_with_format(path, format)
+    };
+    result.map_err(|source| Error::Image { label: label(), source })
+}
@@ -1,5 +1,9 @@
-use image::GenericImageView;
-use anyhow::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use crate::error::{Error, Result};
+use crate::loader::{ImageKey, ImageLoader};
+use crate::scene::ImageSource;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -14,7 +18,8 @@ impl Texture {
         bytes: &[u8], 
         label: &str
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
+        let img = image::load_from_memory(bytes)
+            .map_err(|source| Error::Image { label: label.to_string(), source })?;
         Self::from_image(device, queue, &img, Some(label))
     }
 
@@ -24,8 +29,16 @@ impl Texture {
         img: &image::DynamicImage,
         label: Option<&str>
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        Self::from_rgba(device, queue, &img.to_rgba8(), label)
+    }
+
+    pub fn from_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &image::RgbaImage,
+        label: Option<&str>
+    ) -> Result<Self> {
+        let dimensions = rgba.dimensions();
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -52,7 +65,7 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(4 * dimensions.0),
@@ -77,3 +90,57 @@ impl Texture {
         Ok(Self { texture, view, sampler })
     }
 }
+
+/// Textures of scene images, shared between renderers on one device so that every image is
+/// decoded and uploaded once for each size it is needed at. Textures live as long as some
+/// renderer uses them; decoded images stay in memory up to `capacity` bytes.
+pub struct TextureCache {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    textures: HashMap<ImageKey, Weak<Texture>>,
+    loader: Arc<ImageLoader>,
+}
+
+impl TextureCache {
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>, capacity: usize) -> Self {
+        Self { device, queue, textures: HashMap::new(), loader: Arc::new(ImageLoader::new(capacity)) }
+    }
+
+    pub fn device(&self) -> (&Arc<wgpu::Device>, &Arc<wgpu::Queue>) {
+        (&self.device, &self.queue)
+    }
+
+    /// The loader behind the cache, for decoding images ahead of `get_all` off the calling
+    /// thread. Images are found again as long as the decoded ones are kept.
+    pub fn loader(&self) -> &Arc<ImageLoader> {
+        &self.loader
+    }
+
+    /// The texture of `image` scaled down to fit `size`, loading it unless a renderer still
+    /// holds it.
+    pub fn get(&mut self, image: &ImageSource, size: [u32; 2]) -> Result<Arc<Texture>> {
+        Ok(self.get_all(&[(image, size)])?.remove(0))
+    }
+
+    /// Textures for several images, decoding those not on the gpu in parallel.
+    pub fn get_all(&mut self, requests: &[(&ImageSource, [u32; 2])]) -> Result<Vec<Arc<Texture>>> {
+        let keys = requests.iter().map(|&(image, size)| ImageKey::new(image, size)).collect::<Vec<_>>();
+        let mut textures = keys.iter().map(|k| self.textures.get(k).and_then(Weak::upgrade)).collect::<Vec<_>>();
+
+        let missing = (0..requests.len()).filter(|&i| textures[i].is_none()).collect::<Vec<_>>();
+        let decoded = self.loader.load_all(&missing.iter().map(|&i| requests[i]).collect::<Vec<_>>());
+        self.textures.retain(|_, t| t.strong_count() > 0);
+        for (i, decoded) in missing.into_iter().zip(decoded) {
+            // the same image may be asked for twice
+            if let Some(texture) = self.textures.get(&keys[i]).and_then(Weak::upgrade) {
+                textures[i] = Some(texture);
+                continue;
+            }
+            let label = requests[i].0.label();
+            let texture = Arc::new(Texture::from_rgba(&self.device, &self.queue, decoded?.as_ref(), Some(&label))?);
+            self.textures.insert(keys[i].clone(), Arc::downgrade(&texture));
+            textures[i] = Some(texture);
+        }
+        Ok(textures.into_iter().map(Option::unwrap).collect())
+    }
+}
@@ -0,0 +1,105 @@
+//! Conversion of rendered frames into the pixel layouts X servers use, with known values.
+
+use xbg::format::PixelFormat;
+use xbg::render::{OutputFormat, RowLayout};
+
+const RGB565: PixelFormat = PixelFormat {
+    depth: 16,
+    bits_per_pixel: 16,
+    scanline_pad: 32,
+    red_mask: 0xf800,
+    green_mask: 0x7e0,
+    blue_mask: 0x1f,
+    big_endian: false,
+};
+
+const BGR24: PixelFormat = PixelFormat { bits_per_pixel: 24, ..PixelFormat::BGRX };
+
+const RGBX: PixelFormat = PixelFormat { red_mask: 0xff, blue_mask: 0xff0000, ..PixelFormat::BGRX };
+
+const DEPTH30: PixelFormat = PixelFormat {
+    depth: 30,
+    red_mask: 0x3ff00000,
+    green_mask: 0xffc00,
+    blue_mask: 0x3ff,
+    ..PixelFormat::BGRX
+};
+
+/// One row of `pixels`, as `output` packs them into u32s, converted to `format`.
+fn convert(format: PixelFormat, output: OutputFormat, pixels: &[u32]) -> Vec<u8> {
+    let rows = RowLayout::new(pixels.len() as u32, 1);
+    let mut src = vec![0; rows.padded_bytes_per_row];
+    for (s, p) in src.chunks_exact_mut(4).zip(pixels) {
+        s.copy_from_slice(&p.to_le_bytes());
+    }
+    let mut dst = vec![0; format.stride(pixels.len() as u16)];
+    format.write_frame(output, rows, &src, &mut dst);
+    dst
+}
+
+#[test]
+fn bgrx_is_copied() {
+    let dst = convert(PixelFormat::BGRX, OutputFormat::Bgra8, &[0x00123456, 0xffabcdef]);
+    assert_eq!(dst, [0x56, 0x34, 0x12, 0x00, 0xef, 0xcd, 0xab, 0xff]);
+}
+
+#[test]
+fn rgbx_swaps_red_and_blue() {
+    let dst = convert(RGBX, OutputFormat::Bgra8, &[0x00123456]);
+    assert_eq!(dst, [0x12, 0x34, 0x56, 0x00]);
+}
+
+#[test]
+fn rgb565_keeps_high_bits() {
+    let dst = convert(RGB565, OutputFormat::Bgra8, &[0x00ffffff, 0x00ff0000, 0x00844221, 0x00000000]);
+    // 0x84 >> 3 = 0x10, 0x42 >> 2 = 0x10, 0x21 >> 3 = 0x04
+    assert_eq!(dst, [0xff, 0xff, 0x00, 0xf8, 0x04, 0x82, 0x00, 0x00]);
+}
+
+#[test]
+fn rgb565_big_endian() {
+    let format = PixelFormat { big_endian: true, ..RGB565 };
+    let dst = convert(format, OutputFormat::Bgra8, &[0x00ffffff, 0x00ff0000]);
+    assert_eq!(dst, [0xff, 0xff, 0xf8, 0x00]);
+}
+
+#[test]
+fn packed_24bpp_rows_are_padded() {
+    // three pixels take 9 bytes, padded to 12
+    let dst = convert(BGR24, OutputFormat::Bgra8, &[0x00123456, 0x00abcdef, 0x00ffffff]);
+    assert_eq!(dst, [0x56, 0x34, 0x12, 0xef, 0xcd, 0xab, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn bgrx_big_endian() {
+    let format = PixelFormat { big_endian: true, ..PixelFormat::BGRX };
+    let dst = convert(format, OutputFormat::Bgra8, &[0x00123456]);
+    assert_eq!(dst, [0x00, 0x12, 0x34, 0x56]);
+}
+
+#[test]
+fn depth30_from_8_bit_replicates_bits() {
+    let dst = convert(DEPTH30, OutputFormat::Bgra8, &[0x00ffffff, 0x00800000, 0x00000001]);
+    let pixels = dst.chunks_exact(4).map(|p| u32::from_le_bytes(p.try_into().unwrap())).collect::<Vec<_>>();
+    // full intensity stays full: 0xff becomes 0x3ff, not 0x3fc
+    assert_eq!(pixels[0], 0x3fffffff);
+    // 0x80 = 0b10000000 becomes 0b1000000010
+    assert_eq!(pixels[1], 0x202 << 20);
+    // 0b00000001 becomes 0b0000000100
+    assert_eq!(pixels[2], 0x004);
+}
+
+#[test]
+fn depth30_from_10_bit_is_copied() {
+    let dst = convert(DEPTH30, OutputFormat::Bgr10, &[0x3ff00201]);
+    assert_eq!(dst, 0x3ff00201u32.to_le_bytes());
+}
+
+#[test]
+fn bgrx_from_10_bit_keeps_high_bits() {
+    let red = 0x3ff << 20;
+    let green = 0x200 << 10;
+    let blue = 0x003;
+    let dst = convert(PixelFormat::BGRX, OutputFormat::Bgr10, &[red | green | blue]);
+    assert_eq!(dst, [0x00, 0x80, 0xff, 0x00]);
+}
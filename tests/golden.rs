@@ -0,0 +1,235 @@
+//! Renders reference scenes on wgpu's software adapter and compares them to the images in
+//! `tests/golden`. Run with `XBG_BLESS=1` to write the references from the current output.
+//! Skipped on machines without a software adapter, unless `XBG_REQUIRE_ADAPTER` is set.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use xbg::monitor::Monitor;
+use xbg::render::{OutputFormat, RendererBuilder};
+use xbg::scene::{Layer, Motion, Placement, Scene};
+
+/// pixels whose YIQ distance exceeds this fraction of the maximum count as different
+const THRESHOLD: f32 = 0.1;
+/// fraction of differing pixels tolerated, for rasterization differences between drivers
+const MAX_DIFFERENT: f32 = 0.001;
+
+/// Renders one frame, or returns `None` if this machine has no software adapter.
+async fn render(
+    scene: &Scene,
+    size: [u16; 2],
+    monitors: &[Monitor],
+    t: f32,
+    format: OutputFormat,
+) -> Option<image::RgbaImage> {
+    let builder = RendererBuilder::new(size)
+        .monitors(monitors)
+        .format(format)
+        .fallback_adapter(true)
+        .scene(scene)
+        .unwrap();
+    let mut rnd = match builder.build().await {
+        Ok(rnd) => rnd,
+        Err(xbg::Error::NoAdapter) => {
+            skip();
+            return None;
+        }
+        Err(e) => panic!("{}", e),
+    };
+
+    let rows = rnd.row_layout();
+    let img = rnd.render(Duration::from_secs_f32(t), |buf| {
+        xbg::snapshot::to_rgba(format, rows, &buf)
+    }).await.unwrap();
+    Some(img)
+}
+
+/// Skips a test for lack of a software adapter, or fails it if `XBG_REQUIRE_ADAPTER` is set.
+fn skip() {
+    if std::env::var_os("XBG_REQUIRE_ADAPTER").is_some() {
+        panic!("no software adapter");
+    }
+    eprintln!("no software adapter, skipping");
+}
+
+/// Perceived color difference as in pixelmatch; 0 for equal colors, 35215 at most.
+fn yiq_delta(a: &image::Rgba<u8>, b: &image::Rgba<u8>) -> f32 {
+    let yiq = |p: &image::Rgba<u8>| {
+        let [r, g, b] = [p[0], p[1], p[2]].map(f32::from);
+        [
+            0.298_895 * r + 0.586_622 * g + 0.114_482 * b,
+            0.595_978 * r - 0.274_176 * g - 0.321_802 * b,
+            0.211_470 * r - 0.522_617 * g + 0.311_147 * b,
+        ]
+    };
+    let ([y1, i1, q1], [y2, i2, q2]) = (yiq(a), yiq(b));
+    0.5053 * (y1 - y2).powi(2) + 0.299 * (i1 - i2).powi(2) + 0.1957 * (q1 - q2).powi(2)
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.png", name))
+}
+
+/// Compares `actual` with `tests/golden/<name>.png`, or writes it there with `XBG_BLESS`.
+fn check(name: &str, actual: &image::RgbaImage) {
+    if std::env::var_os("XBG_BLESS").is_some() {
+        actual.save(golden_path(name)).unwrap();
+    } else {
+        compare(name, actual);
+    }
+}
+
+/// On failure, the actual image and a diff with differing pixels in red are written to the
+/// cargo target directory.
+fn compare(name: &str, actual: &image::RgbaImage) {
+    let golden = golden_path(name);
+    let expected = image::open(&golden)
+        .unwrap_or_else(|e| panic!("{}: {} (run with XBG_BLESS=1 to create it)", golden.display(), e))
+        .into_rgba8();
+    assert_eq!(expected.dimensions(), actual.dimensions(), "{}: size differs", name);
+
+    let max_delta = 35215.0 * THRESHOLD * THRESHOLD;
+    let mut diff = image::RgbaImage::new(actual.width(), actual.height());
+    let mut different = 0;
+    for ((e, a), d) in expected.pixels().zip(actual.pixels()).zip(diff.pixels_mut()) {
+        *d = if yiq_delta(e, a) > max_delta {
+            different += 1;
+            image::Rgba([255, 0, 0, 255])
+        } else {
+            // faded expected image for context
+            let l = (e[0] as u16 + e[1] as u16 + e[2] as u16) / 3;
+            let l = (l / 4 + 191) as u8;
+            image::Rgba([l, l, l, 255])
+        };
+    }
+
+    let total = (actual.width() * actual.height()) as f32;
+    if different as f32 > total * MAX_DIFFERENT {
+        let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        std::fs::create_dir_all(&out).unwrap();
+        actual.save(out.join(format!("{}.actual.png", name))).unwrap();
+        diff.save(out.join(format!("{}.diff.png", name))).unwrap();
+        panic!(
+            "{}: {} of {} pixels differ from {}; see {}",
+            name, different, total, golden.display(), out.display(),
+        );
+    }
+}
+
+#[tokio::test]
+async fn demo_single_monitor() {
+    let Some(img) = render(&Scene::demo(), [160, 90], &[Monitor::from([0, 0, 160, 90])], 0.0, OutputFormat::Bgra8).await else { return };
+    check("demo_single_monitor", &img);
+}
+
+#[tokio::test]
+async fn demo_two_monitors_animated() {
+    let monitors = [[0, 0, 128, 96], [128, 16, 96, 64]].map(Monitor::from);
+    let Some(img) = render(&Scene::demo(), [224, 96], &monitors, 1.5, OutputFormat::Bgra8).await else { return };
+    check("demo_two_monitors_animated", &img);
+}
+
+#[tokio::test]
+async fn bob_motion_over_time() {
+    let scene = Scene {
+        layers: vec![Layer {
+            image: xbg::scene::ImageSource::Embedded("favicon.png", include_bytes!("../src/favicon.png")),
+            motion: Motion::Bob { amplitude: 0.25, speed: 2.0 },
+            placement: Placement::Monitor,
+            size: None,
+        }],
+        bezel: [0.0; 2],
+    };
+    for ms in [0, 800] {
+        let t = ms as f32 / 1000.0;
+        let Some(img) = render(&scene, [64, 64], &[Monitor::from([0, 0, 64, 64])], t, OutputFormat::Bgra8).await else { return };
+        check(&format!("bob_{}ms", ms), &img);
+    }
+}
+
+// 10 bit output carries the same picture; truncated to 8 bits it matches the 8 bit reference
+#[tokio::test]
+async fn bgr10_matches_bgra8() {
+    let Some(img) = render(&Scene::demo(), [160, 90], &[Monitor::from([0, 0, 160, 90])], 0.0, OutputFormat::Bgr10).await else { return };
+    compare("demo_single_monitor", &img);
+}
+
+// renderers sharing a device and texture cache draw the same as ones on their own
+#[tokio::test]
+async fn shared_texture_cache() {
+    let (device, queue) = match xbg::render::request_device(true).await {
+        Ok(dq) => dq,
+        Err(xbg::Error::NoAdapter) => return skip(),
+        Err(e) => panic!("{}", e),
+    };
+    let mut cache = xbg::texture::TextureCache::new(device, queue, xbg::loader::DEFAULT_CAPACITY);
+    let scene = Scene::demo();
+    // alive renderers keep their textures in the cache
+    let mut renderers = Vec::new();
+    for (size, monitors, t, name) in [
+        ([160, 90], vec![[0, 0, 160, 90]], 0.0, "demo_single_monitor"),
+        ([224, 96], vec![[0, 0, 128, 96], [128, 16, 96, 64]], 1.5, "demo_two_monitors_animated"),
+    ] {
+        let mut rnd = RendererBuilder::new(size)
+            .monitors(&monitors.into_iter().map(Monitor::from).collect::<Vec<_>>())
+            .cached_scene(&scene, &mut cache)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let rows = rnd.row_layout();
+        let img = rnd.render(Duration::from_secs_f32(t), |buf| {
+            xbg::snapshot::to_rgba(OutputFormat::Bgra8, rows, &buf)
+        }).await.unwrap();
+        compare(name, &img);
+        renderers.push(rnd);
+    }
+}
+
+// a portrait monitor, and one reaching past the left edge as randr may report it
+#[tokio::test]
+async fn portrait_and_offset_monitors() {
+    let monitors = [
+        Monitor { x: 0, y: 0, width: 64, height: 96, ..Default::default() },
+        Monitor { x: 40, y: 16, width: 64, height: 64, ..Default::default() },
+        Monitor { x: -32, y: 80, width: 64, height: 16, ..Default::default() },
+    ];
+    let Some(img) = render(&Scene::demo(), [128, 96], &monitors, 0.0, OutputFormat::Bgra8).await else { return };
+    check("portrait_and_offset_monitors", &img);
+}
+
+// one image over a dense and a coarse panel, skipping what the bezels between them hide
+#[tokio::test]
+async fn span_with_bezel() {
+    let monitors = [
+        Monitor { x: 0, y: 0, width: 96, height: 64, width_mm: 48, height_mm: 32, ..Default::default() },
+        Monitor { x: 96, y: 0, width: 64, height: 64, width_mm: 64, height_mm: 64, ..Default::default() },
+    ];
+    let scene = Scene {
+        layers: vec![Layer {
+            image: xbg::scene::ImageSource::Embedded("happy-tree.png", include_bytes!("../src/happy-tree.png")),
+            motion: Motion::None,
+            placement: Placement::Span,
+            size: None,
+        }],
+        bezel: [16.0, 0.0],
+    };
+    let Some(img) = render(&scene, [160, 64], &monitors, 0.0, OutputFormat::Bgra8).await else { return };
+    check("span_with_bezel", &img);
+}
+
+// a sprite of fixed logical size comes out twice as large on a monitor of scale 2, and
+// unstretched on a portrait monitor
+#[tokio::test]
+async fn logical_size_per_monitor() {
+    let monitors = [
+        Monitor { x: 0, y: 0, width: 64, height: 64, scale: Some(1.0), ..Default::default() },
+        Monitor { x: 64, y: 0, width: 64, height: 64, scale: Some(2.0), ..Default::default() },
+        Monitor { x: 128, y: 0, width: 48, height: 64, ..Default::default() },
+    ];
+    let mut scene = Scene::demo();
+    scene.layers[1].motion = Motion::None;
+    scene.layers[1].size = Some([24.0, 16.0]);
+    let Some(img) = render(&scene, [176, 64], &monitors, 0.0, OutputFormat::Bgra8).await else { return };
+    check("logical_size_per_monitor", &img);
+}
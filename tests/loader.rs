@@ -0,0 +1,40 @@
+//! Scaling and caching of decoded layer images.
+
+use std::sync::Arc;
+
+use xbg::loader::ImageLoader;
+use xbg::scene::ImageSource;
+
+const TREE: ImageSource = ImageSource::Embedded("happy-tree.png", include_bytes!("../src/happy-tree.png"));
+
+#[test]
+fn scales_down_but_not_up() {
+    let loader = ImageLoader::new(0);
+    let full = loader.load(&TREE, [u32::MAX; 2]).unwrap();
+    let small = loader.load(&TREE, [32, 16]).unwrap();
+    assert_eq!(small.dimensions(), (32, 16));
+    let wide = loader.load(&TREE, [full.width() * 2, 16]).unwrap();
+    assert_eq!(wide.dimensions(), (full.width(), 16));
+}
+
+#[test]
+fn keeps_recently_used_images() {
+    // room for two 16x16 images
+    let loader = ImageLoader::new(2 * 16 * 16 * 4);
+    let a = loader.load(&TREE, [16, 16]).unwrap();
+    let b = Arc::downgrade(&loader.load(&TREE, [16, 15]).unwrap());
+    assert!(Arc::ptr_eq(&a, &loader.load(&TREE, [16, 16]).unwrap()));
+
+    // b is the least recently used and goes first
+    let c = loader.load(&TREE, [15, 16]).unwrap();
+    assert!(Arc::ptr_eq(&a, &loader.load(&TREE, [16, 16]).unwrap()));
+    assert!(b.upgrade().is_none());
+
+    // images held elsewhere are found after they are dropped from memory
+    loader.load(&TREE, [14, 16]).unwrap();
+    assert!(Arc::ptr_eq(&c, &loader.load(&TREE, [15, 16]).unwrap()));
+
+    // the same image asked for twice at once is decoded once
+    let both = loader.load_all(&[(&TREE, [8, 8]), (&TREE, [8, 8])]);
+    assert!(Arc::ptr_eq(both[0].as_ref().unwrap(), both[1].as_ref().unwrap()));
+}
@@ -0,0 +1,109 @@
+//! Frame-rate policy on fake sysfs trees.
+
+use std::path::{Path, PathBuf};
+
+use xbg::power::{PowerConfig, PowerPolicy, PowerState};
+
+/// A fresh sysfs root under the target directory, holding `files` as `(path, content)`.
+fn sysfs(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join("sysfs").join(name);
+    let _ = std::fs::remove_dir_all(&root);
+    for (path, content) in files {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, format!("{}\n", content)).unwrap();
+    }
+    root
+}
+
+fn policy(root: &Path) -> PowerPolicy {
+    PowerPolicy::new(root, PowerConfig::default())
+}
+
+#[test]
+fn on_ac() {
+    let root = sysfs("on_ac", &[
+        ("class/power_supply/AC/type", "Mains"),
+        ("class/power_supply/AC/online", "1"),
+        ("class/power_supply/BAT0/type", "Battery"),
+        ("class/power_supply/BAT0/status", "Charging"),
+        ("class/power_supply/BAT0/capacity", "10"),
+    ]);
+    let policy = policy(&root);
+    let state = policy.read_state();
+    assert_eq!(state, PowerState { on_battery: false, capacity: Some(10), temperature: None });
+    assert_eq!(policy.fps_for(&state), PowerConfig::default().fps);
+}
+
+#[test]
+fn discharging() {
+    let root = sysfs("discharging", &[
+        ("class/power_supply/AC/type", "Mains"),
+        ("class/power_supply/AC/online", "0"),
+        ("class/power_supply/BAT0/type", "Battery"),
+        ("class/power_supply/BAT0/status", "Discharging"),
+        ("class/power_supply/BAT0/capacity", "80"),
+    ]);
+    let policy = policy(&root);
+    assert!(policy.read_state().on_battery);
+    assert_eq!(policy.fps(), PowerConfig::default().battery_fps);
+}
+
+#[test]
+fn low_battery() {
+    let root = sysfs("low_battery", &[
+        ("class/power_supply/BAT0/type", "Battery"),
+        ("class/power_supply/BAT0/status", "Discharging"),
+        ("class/power_supply/BAT0/capacity", "85"),
+        ("class/power_supply/BAT1/type", "Battery"),
+        ("class/power_supply/BAT1/status", "Discharging"),
+        ("class/power_supply/BAT1/capacity", "15"),
+    ]);
+    let policy = policy(&root);
+    // the emptiest battery counts
+    assert_eq!(policy.read_state().capacity, Some(15));
+    assert_eq!(policy.fps(), PowerConfig::default().low_battery_fps);
+}
+
+#[test]
+fn hot_zone() {
+    let root = sysfs("hot_zone", &[
+        ("class/power_supply/AC/type", "Mains"),
+        ("class/power_supply/AC/online", "1"),
+        ("class/thermal/thermal_zone0/temp", "45000"),
+        ("class/thermal/thermal_zone1/temp", "91500"),
+        // cooling devices live next to the zones and are not temperatures
+        ("class/thermal/cooling_device0/temp", "120000"),
+    ]);
+    let policy = policy(&root);
+    assert_eq!(policy.read_state().temperature, Some(91.5));
+    assert_eq!(policy.fps(), PowerConfig::default().hot_fps);
+}
+
+#[test]
+fn peripheral_battery_ignored() {
+    let root = sysfs("peripheral_battery", &[
+        ("class/power_supply/AC/type", "Mains"),
+        ("class/power_supply/AC/online", "1"),
+        ("class/power_supply/hidpp_battery_0/type", "Battery"),
+        ("class/power_supply/hidpp_battery_0/scope", "Device"),
+        ("class/power_supply/hidpp_battery_0/status", "Discharging"),
+        ("class/power_supply/hidpp_battery_0/capacity", "5"),
+    ]);
+    let policy = policy(&root);
+    assert_eq!(policy.read_state(), PowerState::default());
+    assert_eq!(policy.fps(), PowerConfig::default().fps);
+}
+
+#[test]
+fn missing_files() {
+    // a desktop without batteries or thermal zones, and a battery with nothing to read
+    let root = sysfs("missing_files", &[("class/power_supply/BAT0/type", "Battery")]);
+    let policy = policy(&root);
+    assert_eq!(policy.read_state(), PowerState::default());
+    assert_eq!(policy.fps(), PowerConfig::default().fps);
+
+    let policy = PowerPolicy::new(root.join("nonexistent"), PowerConfig::default());
+    assert_eq!(policy.read_state(), PowerState::default());
+    assert_eq!(policy.fps(), PowerConfig::default().fps);
+}
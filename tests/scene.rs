@@ -0,0 +1,21 @@
+//! Parsing of scene files.
+
+use xbg::scene::Scene;
+
+const LAYER: &str = "[[layer]]\nimage = \"tree.png\"\n";
+
+#[test]
+fn bezel_defaults_to_zero() {
+    let scene: Scene = toml::from_str(LAYER).unwrap();
+    assert_eq!(scene.bezel, [0.0; 2]);
+}
+
+#[test]
+fn bezel_rejects_negative_and_infinite_gaps() {
+    let scene: Scene = toml::from_str(&format!("bezel = [12.5, 0]\n{}", LAYER)).unwrap();
+    assert_eq!(scene.bezel, [12.5, 0.0]);
+    for bezel in ["[-1, 0]", "[0, -0.5]", "[inf, 0]", "[0, nan]"] {
+        let text = format!("bezel = {}\n{}", bezel, LAYER);
+        assert!(toml::from_str::<Scene>(&text).is_err(), "{} was accepted", bezel);
+    }
+}
@@ -0,0 +1,258 @@
+//! Runs the daemon against a private Xvfb and checks what it leaves on the root window.
+//! Skipped if `Xvfb` is not installed, unless `XBG_REQUIRE_XVFB` is set; monitors are split
+//! with `xrandr` if that is available.
+
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use x11rb::connection::Connection;
+use x11rb::protocol::randr::ConnectionExt as RandrConnectionExt;
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, ImageFormat, WindowClass};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+const WIDTH: u16 = 640;
+const HEIGHT: u16 = 240;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static NEXT_DISPLAY: AtomicU32 = AtomicU32::new(90);
+
+struct Xvfb {
+    child: Child,
+    display: String,
+}
+
+impl Xvfb {
+    /// Starts a server on an unused display, or returns `None` if Xvfb can't be run.
+    fn start() -> Option<Self> {
+        let n = loop {
+            let n = NEXT_DISPLAY.fetch_add(1, Ordering::Relaxed);
+            if !Path::new(&format!("/tmp/.X{}-lock", n)).exists() {
+                break n;
+            }
+        };
+        let display = format!(":{}", n);
+        let child = Command::new("Xvfb")
+            .args([&display, "-screen", "0", &format!("{}x{}x24", WIDTH, HEIGHT), "-nolisten", "tcp"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(e) if std::env::var_os("XBG_REQUIRE_XVFB").is_some() => panic!("Xvfb: {}", e),
+            Err(_) => {
+                eprintln!("Xvfb not available, skipping");
+                return None;
+            }
+        };
+        let xvfb = Self { child, display };
+
+        let socket = format!("/tmp/.X11-unix/X{}", n);
+        wait_until("Xvfb to start", || Path::new(&socket).exists());
+
+        // two side by side monitors, if xrandr can set them up
+        let split = WIDTH / 2;
+        for (name, x) in [("left", 0), ("right", split)] {
+            let geometry = format!("{}/0x{}/0+{}+0", split, HEIGHT, x);
+            let _ = Command::new("xrandr")
+                .args(["-display", &xvfb.display, "--setmonitor", name, &geometry, "none"])
+                .stderr(Stdio::null())
+                .status();
+        }
+        Some(xvfb)
+    }
+
+    fn connect(&self) -> RustConnection {
+        x11rb::connect(Some(&self.display)).unwrap().0
+    }
+
+    fn xbg(&self, args: &[&str]) -> Child {
+        Command::new(env!("CARGO_BIN_EXE_xbg"))
+            .args(args)
+            .env("DISPLAY", &self.display)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap()
+    }
+}
+
+impl Drop for Xvfb {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < TIMEOUT, "timed out waiting for {}", what);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn terminate(child: &mut Child) {
+    unsafe { libc::kill(child.id() as i32, libc::SIGTERM); }
+    let status = child.wait().unwrap();
+    assert!(status.success(), "xbg exited with {}", status);
+}
+
+fn root_property(conn: &RustConnection, name: &str) -> Option<u32> {
+    let root = conn.setup().roots[0].root;
+    let atom = conn.intern_atom(false, name.as_bytes()).unwrap().reply().unwrap().atom;
+    let reply = conn.get_property(false, root, atom, AtomEnum::PIXMAP, 0, 1).unwrap().reply().unwrap();
+    reply.value32().and_then(|mut v| v.next())
+}
+
+fn pixmap_exists(conn: &RustConnection, pixmap: u32) -> bool {
+    conn.get_geometry(pixmap).unwrap().reply().is_ok()
+}
+
+/// Root window contents as RGB.
+fn root_image(conn: &RustConnection) -> image::RgbImage {
+    let root = conn.setup().roots[0].root;
+    let reply = conn.get_image(ImageFormat::Z_PIXMAP, root, 0, 0, WIDTH, HEIGHT, !0).unwrap().reply().unwrap();
+    // depth 24 in 32 bit little endian pixels: BGRX
+    let data = reply.data.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0]]).collect();
+    image::RgbImage::from_raw(WIDTH.into(), HEIGHT.into(), data).unwrap()
+}
+
+fn pixmap_image(conn: &RustConnection, pixmap: u32) -> Vec<u8> {
+    conn.get_image(ImageFormat::Z_PIXMAP, pixmap, 0, 0, WIDTH, HEIGHT, !0).unwrap().reply().unwrap().data
+}
+
+/// The frame `xbg render` produces for `image` with the monitor layout of `conn`.
+fn expected_frame(conn: &RustConnection, image: &str) -> image::RgbImage {
+    let root = conn.setup().roots[0].root;
+    let monitors = conn.randr_get_monitors(root, false).unwrap().reply().unwrap().monitors.iter()
+        .map(|m| format!("{}x{}+{}+{}", m.width, m.height, m.x, m.y))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("xvfb-expected-{}.png", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_xbg"))
+        .args(["render", "--image", image, "--size", &format!("{}x{}", WIDTH, HEIGHT), "--monitors", &monitors])
+        .arg("-o").arg(&out)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let img = image::open(&out).unwrap().into_rgb8();
+    std::fs::remove_file(&out).unwrap();
+    img
+}
+
+fn matches(a: &image::RgbImage, b: &image::RgbImage) -> bool {
+    a.as_raw().iter().zip(b.as_raw()).all(|(x, y)| x.abs_diff(*y) <= 2)
+}
+
+/// SysV segments created by process `pid`, from `/proc/sysvipc/shm`.
+fn shm_segments(pid: u32) -> Vec<String> {
+    let table = std::fs::read_to_string("/proc/sysvipc/shm").unwrap_or_default();
+    table.lines().skip(1)
+        .filter(|l| l.split_whitespace().nth(4) == Some(&pid.to_string()))
+        .map(str::to_string)
+        .collect()
+}
+
+fn test_image() -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src/happy-tree.png").display().to_string()
+}
+
+#[test]
+fn publishes_frame_and_cleans_up() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let conn = xvfb.connect();
+    let image = test_image();
+    let expected = expected_frame(&conn, &image);
+
+    let mut xbg = xvfb.xbg(&["--image", &image]);
+    wait_until("the frame to be drawn", || matches(&root_image(&conn), &expected));
+
+    let pixmap = root_property(&conn, "_XROOTPMAP_ID").expect("_XROOTPMAP_ID not set");
+    assert_eq!(root_property(&conn, "ESETROOT_PMAP_ID"), Some(pixmap));
+    assert!(pixmap_exists(&conn, pixmap));
+
+    let pid = xbg.id();
+    terminate(&mut xbg);
+    assert_eq!(root_property(&conn, "_XROOTPMAP_ID"), None);
+    assert!(!pixmap_exists(&conn, pixmap));
+    wait_until("shared memory to be released", || shm_segments(pid).is_empty());
+}
+
+#[test]
+fn keeps_frame_on_exit() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let conn = xvfb.connect();
+    let image = test_image();
+    let expected = expected_frame(&conn, &image);
+
+    let mut xbg = xvfb.xbg(&["--image", &image, "--keep-on-exit"]);
+    wait_until("the frame to be drawn", || matches(&root_image(&conn), &expected));
+    let pid = xbg.id();
+    terminate(&mut xbg);
+
+    let pixmap = root_property(&conn, "_XROOTPMAP_ID").expect("_XROOTPMAP_ID not set");
+    assert!(pixmap_exists(&conn, pixmap));
+    assert!(matches(&root_image(&conn), &expected));
+    wait_until("shared memory to be released", || shm_segments(pid).is_empty());
+}
+
+#[test]
+fn animates_and_frees_previous_setter() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let conn = xvfb.connect();
+
+    // a retained pixmap, as left behind by `--exit` or other setters
+    let status = Command::new(env!("CARGO_BIN_EXE_xbg"))
+        .args(["--image", &test_image(), "--exit"])
+        .env("DISPLAY", &xvfb.display)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let previous = root_property(&conn, "_XROOTPMAP_ID").expect("_XROOTPMAP_ID not set");
+    assert!(pixmap_exists(&conn, previous));
+
+    // the demo scene moves, so consecutive frames differ
+    let mut xbg = xvfb.xbg(&[]);
+    wait_until("the previous pixmap to be freed", || !pixmap_exists(&conn, previous));
+    let first = root_image(&conn);
+    wait_until("the next frame", || root_image(&conn) != first);
+
+    let pid = xbg.id();
+    terminate(&mut xbg);
+    wait_until("shared memory to be released", || shm_segments(pid).is_empty());
+}
+
+#[test]
+fn leaves_root_window_to_compositor() {
+    let Some(xvfb) = Xvfb::start() else { return };
+    let conn = xvfb.connect();
+    let root = conn.setup().roots[0].root;
+
+    // pose as a compositing manager
+    let owner = conn.generate_id().unwrap();
+    conn.create_window(0, owner, root, 0, 0, 1, 1, 0, WindowClass::INPUT_ONLY, 0, &Default::default()).unwrap();
+    let selection = conn.intern_atom(false, b"_NET_WM_CM_S0").unwrap().reply().unwrap().atom;
+    conn.set_selection_owner(owner, selection, x11rb::CURRENT_TIME).unwrap();
+    conn.sync().unwrap();
+    let before = root_image(&conn);
+
+    // frames go into the pixmap only
+    let mut xbg = xvfb.xbg(&[]);
+    wait_until("_XROOTPMAP_ID to be set", || root_property(&conn, "_XROOTPMAP_ID").is_some());
+    let pixmap = root_property(&conn, "_XROOTPMAP_ID").unwrap();
+    let first = pixmap_image(&conn, pixmap);
+    wait_until("the next frame", || pixmap_image(&conn, pixmap) != first);
+    assert!(root_image(&conn) == before);
+
+    // once the compositor is gone, the root window shows them again
+    conn.destroy_window(owner).unwrap();
+    conn.sync().unwrap();
+    wait_until("the root window to be drawn", || root_image(&conn) != before);
+
+    terminate(&mut xbg);
+}